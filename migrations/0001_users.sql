@@ -0,0 +1,17 @@
+CREATE TABLE users (
+    username TEXT PRIMARY KEY NOT NULL,
+    display_name TEXT,
+    description TEXT,
+    date_created TEXT,
+    related_link TEXT,
+    location TEXT,
+    following INTEGER,
+    followers INTEGER,
+    pfp_url TEXT,
+    banner_url TEXT,
+    fetched_at TEXT,
+    -- Set when the last fetch of the user failed, and cleared once one succeeds
+    error_kind TEXT,
+    error_message TEXT,
+    error_at TEXT
+);
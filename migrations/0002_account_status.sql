@@ -0,0 +1,11 @@
+ALTER TABLE users ADD COLUMN status TEXT;
+ALTER TABLE users ADD COLUMN status_changed_at TEXT;
+
+-- Every time a tracked account changed status, i.e. it got suspended
+CREATE TABLE account_status_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL REFERENCES users (username),
+    old_status TEXT NOT NULL,
+    new_status TEXT NOT NULL,
+    changed_at TEXT NOT NULL
+);
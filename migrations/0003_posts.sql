@@ -0,0 +1,15 @@
+CREATE TABLE posts (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL,
+    link TEXT NOT NULL,
+    text TEXT NOT NULL,
+    posted_at TEXT NOT NULL,
+    fetched_at TEXT NOT NULL,
+    -- Last time the post was seen still being up
+    checked_at TEXT,
+    -- Set once the post is gone, with what the site showed instead
+    deleted_at TEXT,
+    deleted_reason TEXT
+);
+
+CREATE INDEX posts_username ON posts (username);
@@ -0,0 +1,10 @@
+-- Every version of an edited post. Each version has its own status id
+CREATE TABLE post_revisions (
+    id TEXT PRIMARY KEY NOT NULL,
+    post_id TEXT NOT NULL REFERENCES posts (id),
+    link TEXT NOT NULL,
+    text TEXT NOT NULL,
+    posted_at TEXT NOT NULL
+);
+
+CREATE INDEX post_revisions_post_id ON post_revisions (post_id);
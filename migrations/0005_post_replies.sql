@@ -0,0 +1,3 @@
+ALTER TABLE posts ADD COLUMN in_reply_to TEXT;
+
+CREATE INDEX posts_in_reply_to ON posts (in_reply_to);
@@ -0,0 +1,3 @@
+-- Id of the post this one quotes. Quoted posts are archived too, even when
+-- their author isn't tracked
+ALTER TABLE posts ADD COLUMN quote_of TEXT;
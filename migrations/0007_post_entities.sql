@@ -0,0 +1,3 @@
+-- The text split into mentions, hashtags, links and so on, as JSON
+ALTER TABLE posts ADD COLUMN entities TEXT;
+ALTER TABLE post_revisions ADD COLUMN entities TEXT;
@@ -0,0 +1,7 @@
+-- Where shortened links in posts go, so they can still be followed if the
+-- shortener goes away
+CREATE TABLE short_links (
+    short_url TEXT PRIMARY KEY NOT NULL,
+    expanded_url TEXT NOT NULL,
+    first_seen_at TEXT NOT NULL
+);
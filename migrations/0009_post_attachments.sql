@@ -0,0 +1,4 @@
+-- Polls, link cards and community notes, as JSON
+ALTER TABLE posts ADD COLUMN poll TEXT;
+ALTER TABLE posts ADD COLUMN card TEXT;
+ALTER TABLE posts ADD COLUMN community_note TEXT;
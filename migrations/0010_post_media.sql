@@ -0,0 +1,2 @@
+-- Photos, videos and GIFs attached to the post, as JSON
+ALTER TABLE posts ADD COLUMN media TEXT;
@@ -0,0 +1,2 @@
+-- Where the screenshot of the post was saved, in the media store
+ALTER TABLE posts ADD COLUMN screenshot_path TEXT;
@@ -0,0 +1,17 @@
+-- Where each post was seen. `kind` is the profile tab, and `name` the user
+-- whose profile it is
+CREATE TABLE post_sources (
+    post_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    first_seen_at TEXT NOT NULL,
+    PRIMARY KEY (post_id, kind, name)
+);
+
+CREATE INDEX post_sources_source ON post_sources (kind, name);
+
+-- Posts archived until now were only fetched from the default tab
+INSERT INTO post_sources (post_id, kind, name, first_seen_at)
+SELECT posts.id, 'posts', users.username, posts.fetched_at
+FROM posts
+JOIN users ON users.username = posts.username COLLATE NOCASE;
@@ -0,0 +1,9 @@
+-- Why each user is archived, as of the last run. `via` is the seed account or
+-- list that brought them in, and empty for users listed in the config
+CREATE TABLE tracked_users (
+    username TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    via TEXT NOT NULL,
+    resolved_at TEXT NOT NULL,
+    PRIMARY KEY (username, reason, via)
+);
@@ -0,0 +1,5 @@
+-- `post_sources` isn't only filled from profiles anymore. `kind` is the
+-- profile tab, `search` or `list`, and `name` is the user whose profile it
+-- is, the search's name from the config, or the list's ID. Nothing to change
+-- in the table itself
+SELECT 1;
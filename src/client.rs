@@ -1,37 +1,35 @@
-use color_eyre::eyre::{bail, eyre, Result};
-use fantoccini::{cookies::Cookie, Client, Locator};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use color_eyre::eyre::{bail, Context, Result};
+use fantoccini::{cookies::Cookie, Locator};
 use tracing::{debug, info};
 
-use crate::{config::TwitterConfig, utils::sleep_secs};
+use crate::{config::TwitterConfig, driver_pool::WrappedClient, secrets, wait};
 
-async fn auth(c: &Client, config: &TwitterConfig) -> Result<Cookie<'static>> {
+async fn auth(c: &WrappedClient, config: &TwitterConfig) -> Result<Cookie<'static>> {
     let username = config.username();
     let password = config.password();
 
     c.goto("https://twitter.com/").await?;
-    sleep_secs(5).await;
     if c.source().await?.as_str().contains("This page is down") {
         bail!("Twitter is down");
     }
 
-    c.find(Locator::XPath(
-        "/html/body/div/div/div/div[2]/main/div/div/div[1]/div/div/div[3]/div[5]/a/div",
-    ))
+    wait::for_element(
+        c,
+        Locator::XPath(
+            "/html/body/div/div/div/div[2]/main/div/div/div[1]/div/div/div[3]/div[5]/a/div",
+        ),
+    )
     .await?
     .click()
     .await?;
     debug!("Opened the sign in box");
-    sleep_secs(3).await;
-    c.find(Locator::XPath("/html/body/div[1]/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div/div/div/div[5]/label/div/div[2]/div/input")).await?.click().await?;
+    wait::for_element(c, Locator::XPath("/html/body/div[1]/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div/div/div/div[5]/label/div/div[2]/div/input")).await?.click().await?;
     debug!("Clicked on the username box");
-    sleep_secs(3).await;
-    c.find(Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div/div/div/div[5]/label/div/div[2]/div/input")).await?.send_keys(username).await?;
+    wait::for_element(c, Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div/div/div/div[5]/label/div/div[2]/div/input")).await?.send_keys(username).await?;
     debug!("Typed in the username box");
-    sleep_secs(1).await;
-    c.find(Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div/div/div/div[6]")).await?.click().await?;
+    wait::for_element(c, Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div/div/div/div[6]")).await?.click().await?;
     debug!("Clicked on the next button");
-    sleep_secs(5).await;
+    wait::for_network_idle(c).await?;
 
     if c.source()
         .await?
@@ -39,55 +37,70 @@ async fn auth(c: &Client, config: &TwitterConfig) -> Result<Cookie<'static>> {
         .contains("Enter your phone number")
     {
         debug!("Got the phone confirmation dialog");
-        c.find(Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[1]/div/div[2]/label/div/div[2]/div/input")).await?.send_keys(username).await?;
+        wait::for_element(c, Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[1]/div/div[2]/label/div/div[2]/div/input")).await?.send_keys(username).await?;
         debug!("Inputted the username");
-        sleep_secs(2).await;
-        c.find(Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[2]/div/div/div/div/div")).await?.click().await?;
+        wait::for_element(c, Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[2]/div/div/div/div/div")).await?.click().await?;
         debug!("Clicked on the button");
-        sleep_secs(3).await;
+        wait::for_network_idle(c).await?;
     }
 
-    c.find(Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[1]/div/div/div[3]/div/label/div/div[2]/div[1]/input")).await?.send_keys(password).await?;
+    wait::for_element(c, Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[1]/div/div/div[3]/div/label/div/div[2]/div[1]/input")).await?.send_keys(password).await?;
     debug!("Typed in the password");
-    sleep_secs(3).await;
-    c.find(Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[2]/div/div[1]/div/div/div/div")).await?.click().await?;
+    wait::for_element(c, Locator::XPath("/html/body/div/div/div/div[1]/div[2]/div/div/div/div/div/div[2]/div[2]/div/div/div[2]/div[2]/div[2]/div/div[1]/div/div/div/div")).await?.click().await?;
     debug!("Clicked on the log in button");
-    sleep_secs(7).await;
 
-    Ok(c.get_all_cookies()
-        .await?
-        .iter()
-        .filter(|c| c.name() == "auth_token")
-        .last()
-        .ok_or(eyre!("Failed to get cookie with name `auth_token`"))?
-        .clone())
+    wait::until("the `auth_token` cookie", wait::TIMEOUT, || async {
+        Ok(c.get_all_cookies()
+            .await?
+            .into_iter()
+            .filter(|c| c.name() == "auth_token")
+            .last())
+    })
+    .await
+    .wrap_err("Failed to get cookie with name `auth_token`")
 }
 
-pub async fn set_auth_cookie(c: &Client, config: &TwitterConfig) -> Result<()> {
+pub async fn set_auth_cookie(c: &WrappedClient, config: &TwitterConfig) -> Result<()> {
     info!("Loading auth token");
-    let cached = tokio::fs::File::open(&config.auth_cache_fname).await;
-    if let Ok(mut f) = cached {
+    let key = secrets::auth_cache_key(config).wrap_err("Failed loading auth cache key")?;
+    let cached = tokio::fs::read(&config.auth_cache_fname).await;
+    if let Ok(contents) = cached {
         info!("Found cached auth");
-        let mut contents = vec![];
-        f.read_to_end(&mut contents).await.unwrap();
+        let contents = match (&key, secrets::is_encrypted(&contents)) {
+            (Some(key), true) => secrets::decrypt(key, &contents)?,
+            (None, true) => bail!("Auth cache is encrypted, but no key was provided"),
+            (Some(key), false) => {
+                info!("Auth cache is not encrypted, encrypting it");
+                let encrypted = secrets::encrypt(key, &contents)?;
+                secrets::write_private_file(&config.auth_cache_fname, &encrypted)
+                    .await
+                    .wrap_err("Failed encrypting auth cache")?;
+                contents
+            }
+            (None, false) => contents,
+        };
         // For some reason, Clients can only add cookies with 'static, so
         // this must be leaked
-        let s = String::from_utf8(contents).unwrap().into_boxed_str();
-        let cookie = Cookie::parse(&*Box::leak(s)).unwrap();
+        let s = String::from_utf8(contents)
+            .wrap_err("Cached auth was not UTF-8")?
+            .into_boxed_str();
+        let cookie = Cookie::parse(&*Box::leak(s)).wrap_err("Failed parsing cached auth")?;
         c.goto("https://twitter.com").await?;
         c.delete_all_cookies().await?;
         c.add_cookie(cookie).await?;
-        c.refresh().await?;
+        // Reload with the cookie set
+        c.goto("https://twitter.com").await?;
     } else {
         info!("Reloading auth from site");
         let cookie = auth(c, config).await?;
-        let mut f = tokio::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&config.auth_cache_fname)
+        let contents = cookie.to_string().into_bytes();
+        let contents = match &key {
+            Some(key) => secrets::encrypt(key, &contents)?,
+            None => contents,
+        };
+        secrets::write_private_file(&config.auth_cache_fname, &contents)
             .await
-            .unwrap();
-        f.write_all(cookie.to_string().as_bytes()).await.unwrap();
+            .wrap_err("Failed caching auth")?;
         info!("Successfully fetched and cached auth from site");
     }
     Ok(())
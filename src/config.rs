@@ -1,31 +1,210 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use color_eyre::eyre::{bail, eyre, Context, Result};
 use serde::Deserialize;
-use std::{collections::HashMap, env, io::Read};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    io::Read,
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::fetch::error::{FetchError, FetchErrorKind};
+use crate::fetch::post::Timeline;
+use crate::secrets;
+
+#[derive(Deserialize, Debug)]
+pub struct RetryConfig {
+    pub attempts: usize,
+    pub backoff: u64,
+    pub retryable: Vec<FetchErrorKind>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: 3,
+            backoff: 30,
+            retryable: vec![
+                FetchErrorKind::Driver,
+                FetchErrorKind::Timeout,
+                FetchErrorKind::RateLimited,
+            ],
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Whether a fetch that failed with `e` on its `attempt`th try should be
+    /// tried again.
+    pub fn should_retry(&self, e: &FetchError, attempt: usize) -> bool {
+        attempt < self.attempts && self.retryable.contains(&e.kind())
+    }
+
+    /// How long to wait before the next attempt, doubling every time.
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        Duration::from_secs(self.backoff)
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1) as u32))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyConfig {
+    pub posts_per_run: usize,
+    pub max_age_days: i64,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        VerifyConfig {
+            posts_per_run: 20,
+            max_age_days: 30,
+        }
+    }
+}
 
 #[derive(Deserialize, Debug)]
 pub struct FetchConfig {
     pub max_links_per_fetch: usize,
     pub max_concurrent_users: usize,
     pub max_sessions_per_user: usize,
-    pub fetch_username: String,
+    /// Accounts whose followings are archived
+    #[serde(default)]
+    pub seeds: Vec<String>,
+    /// The single seed account from before `seeds`, added to them
+    #[serde(default)]
+    fetch_username: Option<String>,
+    /// Archived besides the followings of `seeds`
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Never archived, whatever brings them in
+    #[serde(default)]
+    pub exclude: Vec<String>,
     pub max_retries: usize,
     pub users_from_following_retry_delay: usize,
+    #[serde(default)]
+    pub guest_mode: bool,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub verify: VerifyConfig,
+    /// Profile tabs archived for every user
+    #[serde(default = "default_timelines")]
+    pub timelines: Vec<Timeline>,
+    /// Profile tabs archived for some users instead, by lowercase username
+    #[serde(default)]
+    pub user_timelines: HashMap<String, Vec<Timeline>>,
+}
+
+fn default_timelines() -> Vec<Timeline> {
+    vec![Timeline::Posts]
+}
+
+impl FetchConfig {
+    /// Profile tabs archived for `username`.
+    pub fn timelines_for(&self, username: &str) -> &[Timeline] {
+        self.user_timelines
+            .get(&username.to_lowercase())
+            .map(|t| t.as_slice())
+            .unwrap_or(&self.timelines)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Browser {
+    Firefox,
+    Chrome,
+}
+
+impl Browser {
+    pub fn driver_program(&self) -> &'static str {
+        match self {
+            Browser::Firefox => "geckodriver",
+            Browser::Chrome => "chromedriver",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DriverBackend {
+    #[default]
+    Geckodriver,
+    Chromedriver,
+    Remote,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct BrowserConfig {
+    #[serde(default)]
+    pub headless: bool,
+    pub window_size: Option<(u32, u32)>,
+    pub profile_dir: Option<String>,
+    pub user_agent: Option<String>,
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub proxies: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct DriverConfig {
+    #[serde(default)]
+    pub backend: DriverBackend,
     pub driver_count: usize,
     pub base_port: usize,
+    #[serde(default)]
+    pub remote_urls: Vec<String>,
+    pub remote_browser: Option<Browser>,
+    #[serde(default = "default_ready_timeout")]
+    pub ready_timeout: u64,
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+    #[serde(default)]
+    pub browser: BrowserConfig,
+}
+
+fn default_ready_timeout() -> u64 {
+    10
+}
+
+fn default_health_check_interval() -> u64 {
+    60
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: u32,
+    pub guest_requests_per_minute: u32,
+    pub burst: usize,
+    pub backoff_base: u64,
+    pub backoff_max: u64,
+    pub max_retries: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_minute: 60,
+            guest_requests_per_minute: 60,
+            burst: 5,
+            backoff_base: 30,
+            backoff_max: 900,
+            max_retries: 5,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TwitterConfig {
     pub auth_cache_fname: String,
+    pub auth_cache_keyfile: Option<String>,
     // This need to be there, to allow for auth, but they are options as a hack for toml to not
     // error out, and to not have two config structs.
     username: Option<String>,
     password: Option<String>,
+    password_file: Option<String>,
+    password_command: Option<String>,
     css_classes: HashMap<String, Vec<String>>,
     xpaths: HashMap<String, String>,
 }
@@ -57,6 +236,120 @@ impl TwitterConfig {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct StorageConfig {
+    pub database: String,
+    pub media_dir: String,
+    #[serde(default)]
+    pub download_videos: bool,
+    /// Used to mux videos with separate audio
+    pub ffmpeg: Option<String>,
+    /// Whether posts are screenshotted when first archived
+    #[serde(default)]
+    pub screenshots: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            database: "twitarc.db".to_owned(),
+            media_dir: "media".to_owned(),
+            download_videos: false,
+            ffmpeg: None,
+            screenshots: false,
+        }
+    }
+}
+
+/// A search archived as its own feed.
+#[derive(Deserialize, Debug)]
+pub struct SearchConfig {
+    /// Names the feed, so it must be usable as a filename
+    pub name: String,
+    pub query: String,
+}
+
+/// A list whose members are followed, or whose timeline is archived as its
+/// own feed.
+#[derive(Deserialize, Debug)]
+pub struct ListConfig {
+    /// The number at the end of the list's URL
+    pub id: String,
+    /// Names the feed, so it must be usable as a filename
+    pub name: String,
+    /// Whether every member is archived like a followed user
+    #[serde(default)]
+    pub members: bool,
+    /// Whether the list's timeline is archived in a single feed
+    #[serde(default)]
+    pub timeline: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FeedConfig {
+    pub output_dir: String,
+    /// How many items each feed keeps
+    pub max_items: usize,
+    /// Whether a user's replies to themselves are put in the same item
+    #[serde(default)]
+    pub collapse_threads: bool,
+    /// URL `media_dir` is served from, to link screenshots
+    pub media_base_url: Option<String>,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            output_dir: "feeds".to_owned(),
+            max_items: 50,
+            collapse_threads: false,
+            media_base_url: None,
+        }
+    }
+}
+
+/// Checks that each of `names` can be used as the filename of a feed, and that
+/// they're all different. `what` says what they name, for errors.
+fn check_feed_names<'a>(what: &str, names: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let mut seen = HashSet::new();
+    for name in names {
+        if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+            bail!("`{name}` can't be the name of a {what}, it must be a valid filename");
+        }
+        if !seen.insert(name) {
+            bail!("There's more than one {what} called `{name}`");
+        }
+    }
+    Ok(())
+}
+
+#[derive(Subcommand, Debug, Default)]
+pub enum Command {
+    /// Fetch every followed user, and update the feeds
+    #[default]
+    Fetch,
+    /// List archived posts that were deleted
+    Deleted {
+        /// Only list posts from this user
+        user: Option<String>,
+    },
+    /// List the users archived by the last fetch, and why
+    Tracked,
+    /// Download the video streamed from an HLS playlist
+    DownloadVideo {
+        playlist_url: String,
+        /// Where to save it, without extension
+        output: String,
+    },
+}
+
+impl Command {
+    /// Whether the command logs in to the site, and so needs the credentials
+    fn logs_in(&self) -> bool {
+        matches!(self, Command::Fetch)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
     #[serde(rename = "fetch")]
@@ -65,6 +358,18 @@ pub struct Config {
     pub driver_config: DriverConfig,
     #[serde(rename = "twitter")]
     pub twitter_config: TwitterConfig,
+    #[serde(rename = "rate_limit", default)]
+    pub rate_limit_config: RateLimitConfig,
+    #[serde(rename = "storage", default)]
+    pub storage_config: StorageConfig,
+    #[serde(default)]
+    pub searches: Vec<SearchConfig>,
+    #[serde(default)]
+    pub lists: Vec<ListConfig>,
+    #[serde(rename = "feeds", default)]
+    pub feed_config: FeedConfig,
+    #[serde(skip)]
+    pub command: Command,
 }
 
 impl Config {
@@ -79,6 +384,9 @@ impl Config {
 
             #[arg(short, long)]
             password: Option<String>,
+
+            #[command(subcommand)]
+            command: Option<Command>,
         }
 
         let cli_config = CliConfig::parse();
@@ -101,6 +409,24 @@ impl Config {
         let config = String::from_utf8(config).wrap_err("Failed parsing config as UTF-8")?;
         let mut config: Config =
             toml::from_str(&config).wrap_err("Failed parsing config as TOML")?;
+        config.command = cli_config.command.unwrap_or_default();
+        if let Some(seed) = config.fetch_config.fetch_username.take() {
+            warn!("`fetch_username` is deprecated, add {seed} to `seeds` instead");
+            if !config.fetch_config.seeds.contains(&seed) {
+                config.fetch_config.seeds.push(seed);
+            }
+        }
+        check_feed_names("search", config.searches.iter().map(|s| s.name.as_str()))?;
+        check_feed_names("list", config.lists.iter().map(|l| l.name.as_str()))?;
+        // Usernames aren't case sensitive
+        config.fetch_config.user_timelines =
+            std::mem::take(&mut config.fetch_config.user_timelines)
+                .into_iter()
+                .map(|(user, timelines)| (user.to_lowercase(), timelines))
+                .collect();
+        if !config.command.logs_in() {
+            return Ok(config);
+        }
 
         if let Some(username) = cli_config.username {
             config.twitter_config.username = Some(username);
@@ -115,9 +441,67 @@ impl Config {
         } else if let Ok(password) = env::var("TWITTER_PASSWORD") {
             config.twitter_config.password = Some(password);
         } else if config.twitter_config.password.is_none() {
-            bail!("Could not load twitter password from CLI, env, nor config");
+            let password = if let Some(path) = &config.twitter_config.password_file {
+                secrets::read_password_file(path)?
+            } else if let Some(command) = &config.twitter_config.password_command {
+                secrets::run_password_command(command)?
+            } else {
+                bail!("Could not load twitter password from CLI, env, config, file nor command");
+            };
+            config.twitter_config.password = Some(password);
         }
 
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_names_must_be_filenames() {
+        assert!(check_feed_names("search", ["rust", "rust lang", "c++"]).is_ok());
+        assert!(check_feed_names("search", [""]).is_err());
+        assert!(check_feed_names("search", ["../../etc/feed"]).is_err());
+        assert!(check_feed_names("search", ["a/b"]).is_err());
+        assert!(check_feed_names("search", [".."]).is_err());
+    }
+
+    #[test]
+    fn feed_names_must_be_different() {
+        assert!(check_feed_names("search", ["rust", "go", "rust"]).is_err());
+    }
+
+    #[test]
+    fn configs_from_before_the_new_sections_still_load() {
+        let config: Config = toml::from_str(
+            r#"
+            [drivers]
+            driver_count = 9
+            base_port = 8444
+
+            [fetch]
+            max_links_per_fetch = 5
+            max_concurrent_users = 3
+            max_sessions_per_user = 3
+            fetch_username = "gooseiman"
+            max_retries = 5
+            users_from_following_retry_delay = 1
+
+            [twitter]
+            auth_cache_fname = "cached_auth"
+            [twitter.css_classes]
+            [twitter.xpaths]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.driver_config.backend, DriverBackend::Geckodriver);
+        assert_eq!(
+            config.fetch_config.fetch_username.as_deref(),
+            Some("gooseiman")
+        );
+        assert_eq!(config.storage_config.database, "twitarc.db");
+        assert_eq!(config.feed_config.output_dir, "feeds");
+    }
+}
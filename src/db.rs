@@ -0,0 +1,737 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
+use indexmap::IndexMap;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    Row, SqlitePool,
+};
+use std::{collections::HashMap, fmt};
+
+use crate::fetch::error::FetchError;
+use crate::fetch::post::{
+    Archived, CommunityNote, FetchedPost, LinkCard, Media, Poll, PostRevision, Timeline,
+};
+use crate::fetch::text::RichText;
+use crate::fetch::users::{AccountStatus, FetchedUser};
+
+#[derive(Debug, Clone)]
+pub struct StatusChange {
+    pub id: i64,
+    pub username: String,
+    pub old_status: AccountStatus,
+    pub new_status: AccountStatus,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// A post from the archive.
+#[derive(Debug, Clone)]
+pub struct StoredPost {
+    pub id: String,
+    pub username: String,
+    pub link: String,
+    pub text: String,
+    /// Empty for posts archived before entities were
+    pub entities: RichText,
+    pub posted_at: DateTime<Utc>,
+    /// Id of the post this one replies to
+    pub in_reply_to: Option<String>,
+    /// Id of the post this one quotes
+    pub quote_of: Option<String>,
+    /// The quoted post, if it was archived
+    pub quote: Option<Box<StoredPost>>,
+    pub poll: Option<Poll>,
+    pub card: Option<LinkCard>,
+    pub community_note: Option<CommunityNote>,
+    pub media: Vec<Media>,
+    /// Every version of the post, oldest first. Empty if it was never edited
+    pub revisions: Vec<PostRevision>,
+    /// Screenshot of the post, in the media store
+    pub screenshot_path: Option<String>,
+}
+
+/// A post that was archived, and later went missing.
+#[derive(Debug, Clone)]
+pub struct DeletedPost {
+    pub username: String,
+    pub link: String,
+    pub text: String,
+    pub posted_at: DateTime<Utc>,
+    pub deleted_at: DateTime<Utc>,
+    pub deleted_reason: String,
+    pub screenshot_path: Option<String>,
+}
+
+/// Where archived posts were seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source<'a> {
+    /// A tab of a user's profile
+    Timeline { user: &'a str, timeline: Timeline },
+    /// A search from the config, by its name there
+    Search { name: &'a str },
+    /// A list's timeline, by its ID
+    List { id: &'a str },
+}
+
+impl Source<'_> {
+    /// The kind and name the source is stored as in `post_sources`: the tab
+    /// and the user for timelines, `search` and the search's name, or `list`
+    /// and the list's ID.
+    fn parts(&self) -> (&'static str, &str) {
+        match self {
+            Source::Timeline { user, timeline } => (timeline.as_str(), user),
+            Source::Search { name } => ("search", name),
+            Source::List { id } => ("list", id),
+        }
+    }
+}
+
+/// Why a user is archived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackReason {
+    /// Followed by one of the seed accounts
+    Followed {
+        by: String,
+    },
+    ListMember {
+        list: String,
+    },
+    /// Listed in the config
+    Listed,
+}
+
+impl TrackReason {
+    fn parts(&self) -> (&'static str, &str) {
+        match self {
+            TrackReason::Followed { by } => ("followed", by),
+            TrackReason::ListMember { list } => ("list_member", list),
+            TrackReason::Listed => ("listed", ""),
+        }
+    }
+
+    fn from_parts(reason: &str, via: String) -> Result<Self> {
+        match reason {
+            "followed" => Ok(TrackReason::Followed { by: via }),
+            "list_member" => Ok(TrackReason::ListMember { list: via }),
+            "listed" => Ok(TrackReason::Listed),
+            _ => Err(eyre!("Unknown reason for tracking a user: {reason}")),
+        }
+    }
+}
+
+impl fmt::Display for TrackReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackReason::Followed { by } => write!(f, "followed by @{by}"),
+            TrackReason::ListMember { list } => write!(f, "member of list {list}"),
+            TrackReason::Listed => write!(f, "listed in the config"),
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &Option<T>) -> Result<Option<String>> {
+    Ok(value.as_ref().map(serde_json::to_string).transpose()?)
+}
+
+fn from_json<T: DeserializeOwned>(row: &SqliteRow, column: &str) -> Result<Option<T>> {
+    let value: Option<String> = row.try_get(column)?;
+    value
+        .map(|v| serde_json::from_str(&v))
+        .transpose()
+        .wrap_err_with(|| format!("Failed parsing stored `{column}`"))
+}
+
+fn read_entities(row: &SqliteRow) -> Result<RichText> {
+    let entities: Option<String> = row.try_get("entities")?;
+    match entities {
+        Some(entities) => {
+            serde_json::from_str(&entities).wrap_err("Failed parsing stored post entities")
+        }
+        None => Ok(RichText::default()),
+    }
+}
+
+fn read_revision(row: &SqliteRow) -> Result<PostRevision> {
+    Ok(PostRevision {
+        id: row.try_get("id")?,
+        link: row.try_get("link")?,
+        text: row.try_get("text")?,
+        entities: read_entities(row)?,
+        posted_at: row.try_get("posted_at")?,
+    })
+}
+
+/// The archive, where everything fetched ends up.
+pub struct Db {
+    pool: SqlitePool,
+}
+
+impl Db {
+    /// Opens the database at `path`, creating it and running any pending
+    /// migrations.
+    pub async fn open(path: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .wrap_err_with(|| format!("Failed opening database at {path}"))?;
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .wrap_err("Failed running database migrations")?;
+        Ok(Db { pool })
+    }
+
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    pub async fn save_user(&self, username: &str, user: &FetchedUser) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO users (
+                username, display_name, description, date_created, related_link,
+                location, following, followers, pfp_url, banner_url, fetched_at,
+                error_kind, error_message, error_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, NULL, NULL, NULL)
+            ON CONFLICT (username) DO UPDATE SET
+                display_name = excluded.display_name,
+                description = excluded.description,
+                date_created = excluded.date_created,
+                related_link = excluded.related_link,
+                location = excluded.location,
+                following = excluded.following,
+                followers = excluded.followers,
+                pfp_url = excluded.pfp_url,
+                banner_url = excluded.banner_url,
+                fetched_at = excluded.fetched_at,
+                error_kind = NULL,
+                error_message = NULL,
+                error_at = NULL",
+        )
+        .bind(username)
+        .bind(&user.display_name)
+        .bind(&user.description)
+        .bind(&user.date_created)
+        .bind(&user.related_link)
+        .bind(&user.location)
+        .bind(user.following as i64)
+        .bind(user.followers as i64)
+        .bind(&user.pfp_url)
+        .bind(&user.banner_url)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .wrap_err_with(|| format!("Failed saving user {username}"))?;
+        Ok(())
+    }
+
+    /// Records why fetching `username` failed, keeping whatever was fetched
+    /// for them before.
+    pub async fn save_user_error(&self, username: &str, e: &FetchError) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO users (username, error_kind, error_message, error_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (username) DO UPDATE SET
+                error_kind = excluded.error_kind,
+                error_message = excluded.error_message,
+                error_at = excluded.error_at",
+        )
+        .bind(username)
+        .bind(e.kind().as_str())
+        .bind(e.to_string())
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .wrap_err_with(|| format!("Failed saving error for user {username}"))?;
+        Ok(())
+    }
+
+    /// Stores the current status of `username`, recording a change if it was
+    /// known to be something else before. Returns the change, if any.
+    pub async fn set_user_status(
+        &self,
+        username: &str,
+        status: AccountStatus,
+    ) -> Result<Option<StatusChange>> {
+        let mut tx = self.pool.begin().await?;
+        let old_status: Option<String> =
+            sqlx::query_scalar("SELECT status FROM users WHERE username = ?")
+                .bind(username)
+                .fetch_optional(&mut *tx)
+                .await?
+                .flatten();
+        let old_status = old_status.map(|s| s.parse()).transpose()?;
+        if old_status == Some(status) {
+            return Ok(None);
+        }
+
+        let now = Utc::now();
+        sqlx::query(
+            "INSERT INTO users (username, status, status_changed_at) VALUES (?, ?, ?)
+            ON CONFLICT (username) DO UPDATE SET
+                status = excluded.status,
+                status_changed_at = excluded.status_changed_at",
+        )
+        .bind(username)
+        .bind(status.as_str())
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .wrap_err_with(|| format!("Failed saving status for user {username}"))?;
+
+        // The first time a user is seen isn't a change
+        let change = match old_status {
+            Some(old_status) => {
+                let id = sqlx::query(
+                    "INSERT INTO account_status_changes
+                        (username, old_status, new_status, changed_at)
+                    VALUES (?, ?, ?, ?)",
+                )
+                .bind(username)
+                .bind(old_status.as_str())
+                .bind(status.as_str())
+                .bind(now)
+                .execute(&mut *tx)
+                .await?
+                .last_insert_rowid();
+                Some(StatusChange {
+                    id,
+                    username: username.to_owned(),
+                    old_status,
+                    new_status: status,
+                    changed_at: now,
+                })
+            }
+            None => None,
+        };
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    /// The latest `limit` status changes, newest first.
+    pub async fn get_status_changes(&self, limit: usize) -> Result<Vec<StatusChange>> {
+        let rows = sqlx::query(
+            "SELECT id, username, old_status, new_status, changed_at
+            FROM account_status_changes
+            ORDER BY changed_at DESC
+            LIMIT ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(StatusChange {
+                    id: row.try_get("id")?,
+                    username: row.try_get("username")?,
+                    old_status: row.try_get::<String, _>("old_status")?.parse()?,
+                    new_status: row.try_get::<String, _>("new_status")?.parse()?,
+                    changed_at: row.try_get("changed_at")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Replaces the users being archived with `users`, and why each one is.
+    pub async fn set_tracked_users(
+        &self,
+        users: &IndexMap<String, Vec<TrackReason>>,
+    ) -> Result<()> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM tracked_users")
+            .execute(&mut *tx)
+            .await?;
+        for (username, reasons) in users {
+            for reason in reasons {
+                let (reason, via) = reason.parts();
+                sqlx::query(
+                    "INSERT INTO tracked_users (username, reason, via, resolved_at)
+                    VALUES (?, ?, ?, ?)
+                    ON CONFLICT DO NOTHING",
+                )
+                .bind(username)
+                .bind(reason)
+                .bind(via)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The users archived by the last run, and why each one is, by username.
+    pub async fn get_tracked_users(&self) -> Result<IndexMap<String, Vec<TrackReason>>> {
+        let rows = sqlx::query(
+            "SELECT username, reason, via FROM tracked_users
+            ORDER BY username COLLATE NOCASE, reason, via",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut users: IndexMap<String, Vec<TrackReason>> = IndexMap::new();
+        for row in rows {
+            let reason = TrackReason::from_parts(row.try_get("reason")?, row.try_get("via")?)?;
+            users
+                .entry(row.try_get("username")?)
+                .or_default()
+                .push(reason);
+        }
+        Ok(users)
+    }
+
+    pub async fn save_post(&self, post: &FetchedPost) -> Result<()> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO posts (
+                id, username, link, text, entities, posted_at, in_reply_to, quote_of,
+                poll, card, community_note, media, fetched_at, checked_at
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                text = excluded.text,
+                entities = excluded.entities,
+                poll = excluded.poll,
+                card = excluded.card,
+                community_note = excluded.community_note,
+                media = excluded.media,
+                in_reply_to = COALESCE(excluded.in_reply_to, posts.in_reply_to),
+                quote_of = COALESCE(excluded.quote_of, posts.quote_of),
+                fetched_at = excluded.fetched_at,
+                checked_at = excluded.checked_at",
+        )
+        .bind(&post.id)
+        .bind(&post.username)
+        .bind(&post.link)
+        .bind(&post.text)
+        .bind(serde_json::to_string(&post.entities)?)
+        .bind(post.posted_at)
+        .bind(&post.in_reply_to)
+        .bind(post.quote_id())
+        .bind(to_json(&post.poll)?)
+        .bind(to_json(&post.card)?)
+        .bind(to_json(&post.community_note)?)
+        .bind(serde_json::to_string(&post.media)?)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .wrap_err_with(|| format!("Failed saving post {}", post.link))?;
+        let short_links = post
+            .entities
+            .short_links()
+            .chain(post.revisions.iter().flat_map(|r| r.entities.short_links()));
+        for (short_url, expanded_url) in short_links {
+            sqlx::query(
+                "INSERT INTO short_links (short_url, expanded_url, first_seen_at)
+                VALUES (?, ?, ?)
+                ON CONFLICT (short_url) DO NOTHING",
+            )
+            .bind(short_url)
+            .bind(expanded_url)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .wrap_err_with(|| format!("Failed saving short link {short_url}"))?;
+        }
+        for revision in &post.revisions {
+            sqlx::query(
+                "INSERT INTO post_revisions (id, post_id, link, text, entities, posted_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                ON CONFLICT (id) DO NOTHING",
+            )
+            .bind(&revision.id)
+            .bind(&post.id)
+            .bind(&revision.link)
+            .bind(&revision.text)
+            .bind(serde_json::to_string(&revision.entities)?)
+            .bind(revision.posted_at)
+            .execute(&mut *tx)
+            .await
+            .wrap_err_with(|| format!("Failed saving revision {}", revision.link))?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn has_post(&self, id: &str) -> Result<bool> {
+        let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM posts WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(found.is_some())
+    }
+
+    /// What's archived of the posts seen on `source`.
+    pub async fn get_archived(&self, source: Source<'_>) -> Result<Archived> {
+        let (kind, name) = source.parts();
+        let screenshotted = sqlx::query_scalar(
+            "SELECT posts.id FROM posts
+            JOIN post_sources ON post_sources.post_id = posts.id
+            WHERE post_sources.kind = ? AND post_sources.name = ?
+                AND posts.screenshot_path IS NOT NULL",
+        )
+        .bind(kind)
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?;
+        let rows = sqlx::query(
+            "SELECT post_revisions.* FROM post_revisions
+            JOIN post_sources ON post_sources.post_id = post_revisions.post_id
+            WHERE post_sources.kind = ? AND post_sources.name = ?
+            ORDER BY post_revisions.posted_at ASC",
+        )
+        .bind(kind)
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut revisions: HashMap<String, Vec<PostRevision>> = HashMap::new();
+        for row in rows {
+            revisions
+                .entry(row.try_get("post_id")?)
+                .or_default()
+                .push(read_revision(&row)?);
+        }
+        Ok(Archived {
+            screenshotted: screenshotted.into_iter().collect(),
+            revisions,
+        })
+    }
+
+    /// Records that the post with `id` was seen on `source`.
+    pub async fn tag_post(&self, id: &str, source: Source<'_>) -> Result<()> {
+        let (kind, name) = source.parts();
+        sqlx::query(
+            "INSERT INTO post_sources (post_id, kind, name, first_seen_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(id)
+        .bind(kind)
+        .bind(name)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Records that the post with `id` was screenshotted to `path`.
+    pub async fn set_post_screenshot(&self, id: &str, path: &str) -> Result<()> {
+        sqlx::query("UPDATE posts SET screenshot_path = ? WHERE id = ?")
+            .bind(path)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Every tracked user with archived posts.
+    pub async fn get_post_authors(&self) -> Result<Vec<String>> {
+        // Authors of quoted posts are only archived for the quotes
+        let users = sqlx::query_scalar(
+            "SELECT DISTINCT posts.username FROM posts
+            JOIN users ON users.username = posts.username COLLATE NOCASE
+            ORDER BY posts.username",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+
+    /// Users whose `timeline` tab had posts archived from it.
+    pub async fn get_timeline_users(&self, timeline: Timeline) -> Result<Vec<String>> {
+        let users = sqlx::query_scalar(
+            "SELECT DISTINCT name FROM post_sources
+            WHERE kind = ?
+            ORDER BY name",
+        )
+        .bind(timeline.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+
+    pub async fn get_revisions(&self, post_id: &str) -> Result<Vec<PostRevision>> {
+        sqlx::query(
+            "SELECT id, link, text, entities, posted_at FROM post_revisions
+            WHERE post_id = ?
+            ORDER BY posted_at ASC",
+        )
+        .bind(post_id)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(read_revision)
+        .collect()
+    }
+
+    /// Reads a post and its revisions from `row`, without what it quotes.
+    async fn read_post(&self, row: &SqliteRow) -> Result<StoredPost> {
+        let id: String = row.try_get("id")?;
+        let mut entities = read_entities(row)?;
+        // Another post may have said where a link goes
+        let mut short_links = HashMap::new();
+        for short_url in entities.unresolved_short_links() {
+            let expanded: Option<String> =
+                sqlx::query_scalar("SELECT expanded_url FROM short_links WHERE short_url = ?")
+                    .bind(short_url)
+                    .fetch_optional(&self.pool)
+                    .await?;
+            if let Some(expanded) = expanded {
+                short_links.insert(short_url.to_owned(), expanded);
+            }
+        }
+        entities.resolve(&short_links);
+        Ok(StoredPost {
+            revisions: self.get_revisions(&id).await?,
+            id,
+            username: row.try_get("username")?,
+            link: row.try_get("link")?,
+            text: row.try_get("text")?,
+            entities,
+            posted_at: row.try_get("posted_at")?,
+            in_reply_to: row.try_get("in_reply_to")?,
+            quote_of: row.try_get("quote_of")?,
+            quote: None,
+            poll: from_json(row, "poll")?,
+            card: from_json(row, "card")?,
+            community_note: from_json(row, "community_note")?,
+            media: from_json(row, "media")?.unwrap_or_default(),
+            screenshot_path: row.try_get("screenshot_path")?,
+        })
+    }
+
+    /// The latest `limit` posts by `username` that are still up, newest
+    /// first.
+    pub async fn get_user_posts(&self, username: &str, limit: usize) -> Result<Vec<StoredPost>> {
+        let rows = sqlx::query(
+            "SELECT * FROM posts
+            WHERE username = ? AND deleted_at IS NULL
+            ORDER BY posted_at DESC
+            LIMIT ?",
+        )
+        .bind(username)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        self.read_posts(rows).await
+    }
+
+    /// The latest `limit` posts seen on `source` that are still up, newest
+    /// first.
+    pub async fn get_source_posts(
+        &self,
+        source: Source<'_>,
+        limit: usize,
+    ) -> Result<Vec<StoredPost>> {
+        let (kind, name) = source.parts();
+        let rows = sqlx::query(
+            "SELECT posts.* FROM posts
+            JOIN post_sources ON post_sources.post_id = posts.id
+            WHERE post_sources.kind = ? AND post_sources.name = ? AND deleted_at IS NULL
+            ORDER BY posted_at DESC
+            LIMIT ?",
+        )
+        .bind(kind)
+        .bind(name)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        self.read_posts(rows).await
+    }
+
+    /// Reads posts from `rows`, with the posts they quote.
+    async fn read_posts(&self, rows: Vec<SqliteRow>) -> Result<Vec<StoredPost>> {
+        let mut posts = vec![];
+        for row in rows {
+            let mut post = self.read_post(&row).await?;
+            if let Some(quote_of) = &post.quote_of {
+                let quote = sqlx::query("SELECT * FROM posts WHERE id = ?")
+                    .bind(quote_of)
+                    .fetch_optional(&self.pool)
+                    .await?;
+                if let Some(quote) = quote {
+                    post.quote = Some(Box::new(self.read_post(&quote).await?));
+                }
+            }
+            posts.push(post);
+        }
+        Ok(posts)
+    }
+
+    /// Links to up to `limit` posts from the last `max_age` that should be
+    /// checked for still being up, the ones checked longest ago first.
+    pub async fn get_posts_to_verify(
+        &self,
+        limit: usize,
+        max_age: chrono::Duration,
+    ) -> Result<Vec<String>> {
+        let links = sqlx::query_scalar(
+            "SELECT link FROM posts
+            WHERE deleted_at IS NULL AND posted_at >= ?
+            ORDER BY checked_at ASC
+            LIMIT ?",
+        )
+        .bind(Utc::now() - max_age)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(links)
+    }
+
+    /// Records that the post at `link` is still up.
+    pub async fn mark_post_checked(&self, link: &str) -> Result<()> {
+        sqlx::query("UPDATE posts SET checked_at = ? WHERE link = ?")
+            .bind(Utc::now())
+            .bind(link)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Records that the post at `link` is gone, and why.
+    pub async fn mark_post_deleted(&self, link: &str, reason: &str) -> Result<()> {
+        let now = Utc::now();
+        sqlx::query(
+            "UPDATE posts SET checked_at = ?, deleted_at = ?, deleted_reason = ? WHERE link = ?",
+        )
+        .bind(now)
+        .bind(now)
+        .bind(reason)
+        .bind(link)
+        .execute(&self.pool)
+        .await
+        .wrap_err_with(|| format!("Failed marking {link} as deleted"))?;
+        Ok(())
+    }
+
+    /// Every post that went missing, by `username` if given, grouped by user
+    /// and newest first.
+    pub async fn get_deleted_posts(&self, username: Option<&str>) -> Result<Vec<DeletedPost>> {
+        let rows = sqlx::query(
+            "SELECT username, link, text, posted_at, deleted_at, deleted_reason, screenshot_path
+            FROM posts
+            WHERE deleted_at IS NOT NULL AND (?1 IS NULL OR username = ?1)
+            ORDER BY username, posted_at DESC",
+        )
+        .bind(username)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(DeletedPost {
+                    username: row.try_get("username")?,
+                    link: row.try_get("link")?,
+                    text: row.try_get("text")?,
+                    posted_at: row.try_get("posted_at")?,
+                    deleted_at: row.try_get("deleted_at")?,
+                    deleted_reason: row.try_get("deleted_reason")?,
+                    screenshot_path: row.try_get("screenshot_path")?,
+                })
+            })
+            .collect()
+    }
+}
@@ -1,118 +1,600 @@
-use color_eyre::eyre::{Context, Result};
-use fantoccini::{wd::Capabilities, Client, ClientBuilder};
-use serde_json::json;
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use fantoccini::{error::CmdError, Client, ClientBuilder, Locator};
 use std::{
-    mem::ManuallyDrop,
+    collections::VecDeque,
     ops::Deref,
-    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
     time::Duration,
 };
-use tokio::sync::Mutex;
-use tracing::debug;
+use tokio::runtime::Handle;
+use tracing::{debug, error, warn};
+
+mod capabilities;
+mod driver;
 
 use crate::client::set_auth_cookie;
-use crate::config::{DriverConfig, TwitterConfig};
+use crate::config::{
+    Browser, BrowserConfig, DriverBackend, DriverConfig, RateLimitConfig, TwitterConfig,
+};
+use crate::rate_limit::{self, RateLimited, RateLimiter};
+use crate::wait;
+use capabilities::capabilities;
+use driver::Driver;
+
+/// How long `close` waits for clients that are still closing to give their
+/// drivers back.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often `wait_for_client` checks for a free driver.
+const WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Drivers {
+    idle: Mutex<VecDeque<Driver>>,
+    /// How many drivers are in the pool, idle or not. Drivers that can't be
+    /// restarted are taken out of it
+    count: AtomicUsize,
+    health_check_interval: Duration,
+    ready_timeout: Duration,
+}
+
+impl Drivers {
+    fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
 
-struct PoolValue {
-    driver: Child,
-    port: usize,
+    /// Puts `driver` back with the idle ones, restarting it first if it
+    /// stopped working. Drivers that fail to restart are removed.
+    async fn give_back(&self, mut driver: Driver) {
+        if !driver.is_healthy().await {
+            if let Err(e) = driver.restart(self.ready_timeout).await {
+                error!("Failed restarting driver at {}: {e:#}", driver.url());
+                self.remove(driver).await;
+                return;
+            }
+        }
+        lock(self).push_back(driver);
+    }
+
+    /// Takes `driver` out of the pool for good.
+    async fn remove(&self, mut driver: Driver) {
+        warn!("Removing driver at {} from the pool", driver.url());
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        if let Err(e) = driver.kill().await {
+            warn!("Failed killing driver at {}: {e:#}", driver.url());
+        }
+    }
+}
+
+fn lock(drivers: &Drivers) -> MutexGuard<'_, VecDeque<Driver>> {
+    // The pool is only pushed to and popped from, so a panic while holding the
+    // lock can't leave it in an inconsistent state
+    drivers.idle.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether a client should be logged in to the site before being handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    Guest,
+    LoggedIn,
 }
 
 pub struct DriverPool {
-    pool: Mutex<Vec<PoolValue>>,
+    pool: Arc<Drivers>,
+    logged_in_limiter: Arc<RateLimiter>,
+    guest_limiter: Arc<RateLimiter>,
+    browser_config: BrowserConfig,
 }
 
 impl DriverPool {
-    pub fn new(config: &DriverConfig) -> Result<Self> {
-        let mut pool = vec![];
-        for n in 0..config.driver_count {
-            let port = config.base_port + n;
-            let driver = Command::new("geckodriver")
-                .arg("-p")
-                .arg(format!("{port}"))
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
-                .wrap_err("Failed spawning geckodriver process")?;
-            pool.push(PoolValue { driver, port });
-        }
-        let pool = Mutex::new(pool);
-        // Give it some time to warm up
-        std::thread::sleep(Duration::from_secs(1));
-        Ok(DriverPool { pool })
-    }
-
-    pub async fn get_client(&self, config: &TwitterConfig) -> Result<Option<WrappedClient>> {
-        let mut caps = Capabilities::new();
-        caps.insert(
-            "moz:firefoxOptions".into(),
-            json!({
-                "prefs": {
-                    "javascript.enabled": true
-                },
-            }),
-        );
-
-        let mut lock = self.pool.lock().await;
-        let val = lock.pop();
-        drop(lock);
-
-        match val {
-            Some(val) => {
-                let port = val.port;
-                debug!("Returning client using port {port}");
-                let client = ClientBuilder::rustls()
-                    .capabilities(caps)
-                    .connect(&format!("http://localhost:{port}"))
-                    .await
-                    .wrap_err("failed to connect to WebDriver")?;
-                if let Err(e) = set_auth_cookie(&client, config).await {
-                    client.close().await?;
-                    let mut lock = self.pool.lock().await;
-                    lock.push(val);
-                    drop(lock);
-                    return Err(e);
-                }
-                Ok(Some(WrappedClient {
-                    client,
-                    val: ManuallyDrop::new(val),
-                    pool: &self.pool,
-                }))
+    pub async fn new(config: &DriverConfig, rate_limit_config: &RateLimitConfig) -> Result<Self> {
+        let ready_timeout = Duration::from_secs(config.ready_timeout);
+        let mut pool = VecDeque::new();
+        let browser = match config.backend {
+            DriverBackend::Geckodriver => Some(Browser::Firefox),
+            DriverBackend::Chromedriver => Some(Browser::Chrome),
+            DriverBackend::Remote => None,
+        };
+        if let Some(browser) = browser {
+            for id in 0..config.driver_count {
+                let port = config.base_port + id;
+                let driver = Driver::spawn(id, browser, port, ready_timeout).await?;
+                pool.push_back(driver);
+            }
+        } else {
+            let browser = config
+                .remote_browser
+                .ok_or(eyre!("`remote_browser` must be set for the remote backend"))?;
+            if config.remote_urls.is_empty() {
+                bail!("`remote_urls` must not be empty for the remote backend");
+            }
+            for (id, url) in config.remote_urls.iter().enumerate() {
+                pool.push_back(Driver::remote(id, browser, url)?);
             }
-            None => Ok(None),
+        }
+        let pool = Arc::new(Drivers {
+            count: AtomicUsize::new(pool.len()),
+            idle: Mutex::new(pool),
+            health_check_interval: Duration::from_secs(config.health_check_interval),
+            ready_timeout,
+        });
+
+        let logged_in_limiter = Arc::new(RateLimiter::new(
+            "logged in",
+            rate_limit_config.requests_per_minute,
+            rate_limit_config,
+        ));
+        let guest_limiter = Arc::new(RateLimiter::new(
+            "guest",
+            rate_limit_config.guest_requests_per_minute,
+            rate_limit_config,
+        ));
+
+        Ok(DriverPool {
+            pool,
+            logged_in_limiter,
+            guest_limiter,
+            browser_config: config.browser.clone(),
+        })
+    }
+
+    pub fn available(&self) -> usize {
+        lock(&self.pool).len()
+    }
+
+    pub async fn get_client(
+        &self,
+        config: &TwitterConfig,
+        auth: ClientAuth,
+    ) -> Result<Option<WrappedClient>> {
+        let Some(mut lease) = DriverLease::take(&self.pool) else {
+            return Ok(None);
+        };
+        // If anything fails from here on, dropping the lease returns the driver
+        let driver = lease.driver();
+        if !driver.is_healthy().await {
+            if let Err(e) = driver.restart(self.pool.ready_timeout).await {
+                lease.remove().await;
+                return Err(e.wrap_err("Failed restarting unhealthy driver"));
+            }
+        }
+        debug!("Returning {auth:?} client using {}", driver.url());
+        let client = ClientBuilder::rustls()
+            .capabilities(capabilities(driver, &self.browser_config)?)
+            .connect(driver.url().as_str())
+            .await
+            .wrap_err("failed to connect to WebDriver")?;
+        let limiter = match auth {
+            ClientAuth::Guest => Arc::clone(&self.guest_limiter),
+            ClientAuth::LoggedIn => Arc::clone(&self.logged_in_limiter),
+        };
+        let client = WrappedClient {
+            client,
+            auth,
+            limiter,
+            lease: Some(lease),
+        };
+        if auth == ClientAuth::LoggedIn {
+            set_auth_cookie(&client, config).await?;
+        }
+        Ok(Some(client))
+    }
+
+    /// Like [`DriverPool::get_client`], but waits for a driver to be free.
+    /// Errors if getting a client from a free driver fails, or once every
+    /// driver was taken out of the pool.
+    pub async fn wait_for_client(
+        &self,
+        config: &TwitterConfig,
+        auth: ClientAuth,
+    ) -> Result<WrappedClient> {
+        loop {
+            if let Some(client) = self.get_client(config, auth).await? {
+                return Ok(client);
+            }
+            if self.pool.count() == 0 {
+                bail!("Every driver was taken out of the pool");
+            }
+            tokio::time::sleep(WAIT_INTERVAL).await;
         }
     }
 
     pub async fn close(&self) -> Result<()> {
-        let mut v = self.pool.lock().await;
-        for PoolValue { port: _, driver } in v.iter_mut() {
-            driver.kill()?;
-            driver.wait()?;
+        // Clients dropped without being closed give back their driver once
+        // their session is closed, so wait for them before killing anything
+        let start = tokio::time::Instant::now();
+        while self.available() < self.pool.count() && start.elapsed() < CLOSE_TIMEOUT {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let drivers = std::mem::take(&mut *lock(&self.pool));
+        if drivers.len() < self.pool.count() {
+            warn!(
+                "{} drivers were not returned to the pool before closing",
+                self.pool.count() - drivers.len()
+            );
+        }
+        for mut driver in drivers {
+            driver.kill().await?;
         }
         Ok(())
     }
 }
 
-pub struct WrappedClient<'a> {
+/// A driver taken out of the pool, which is put back when this is dropped.
+/// Drivers that weren't checked for `health_check_interval` are checked on
+/// their way back.
+struct DriverLease {
+    driver: Option<Driver>,
+    pool: Arc<Drivers>,
+}
+
+impl DriverLease {
+    fn take(pool: &Arc<Drivers>) -> Option<Self> {
+        let driver = lock(pool).pop_front()?;
+        Some(DriverLease {
+            driver: Some(driver),
+            pool: Arc::clone(pool),
+        })
+    }
+
+    fn driver(&mut self) -> &mut Driver {
+        self.driver.as_mut().expect("Lease was already returned")
+    }
+
+    /// Takes the driver out of the pool instead of returning it.
+    async fn remove(mut self) {
+        if let Some(driver) = self.driver.take() {
+            self.pool.remove(driver).await;
+        }
+    }
+}
+
+impl Drop for DriverLease {
+    fn drop(&mut self) {
+        let Some(driver) = self.driver.take() else {
+            return;
+        };
+        if driver.last_checked().elapsed() < self.pool.health_check_interval {
+            lock(&self.pool).push_back(driver);
+            return;
+        }
+        match Handle::try_current() {
+            Ok(handle) => {
+                let pool = Arc::clone(&self.pool);
+                handle.spawn(async move { pool.give_back(driver).await });
+            }
+            // Checked once it's taken out again instead
+            Err(_) => lock(&self.pool).push_back(driver),
+        }
+    }
+}
+
+/// A client connected to a driver from the pool.
+///
+/// Prefer calling [`WrappedClient::close`], but if this is dropped (i.e. on an
+/// early return or a panic), the session is closed in the background and the
+/// driver is only then given back to the pool, as drivers can only hold one
+/// session at a time. Outside of a runtime the session can't be closed, so the
+/// driver is restarted before it's used again instead.
+pub struct WrappedClient {
     client: Client,
-    val: ManuallyDrop<PoolValue>,
-    pool: &'a Mutex<Vec<PoolValue>>,
+    auth: ClientAuth,
+    limiter: Arc<RateLimiter>,
+    lease: Option<DriverLease>,
 }
 
-impl<'a> WrappedClient<'a> {
+impl WrappedClient {
+    pub fn auth(&self) -> ClientAuth {
+        self.auth
+    }
+
+    /// Goes to `url` once the rate limiter allows it, and waits for the page
+    /// to load. If the site says we are rate limited, backs off and reloads.
+    pub async fn goto(&self, url: &str) -> Result<()> {
+        self.limiter.acquire().await;
+        self.client.goto(url).await?;
+        let mut attempt = 0;
+        while self.is_rate_limited(attempt).await? {
+            self.limiter.acquire().await;
+            self.client.goto(url).await?;
+            attempt += 1;
+        }
+        Ok(())
+    }
+
+    /// Scrolls down by `y` pixels once the rate limiter allows it, and waits
+    /// for whatever that loads.
+    pub async fn scroll_by(&self, y: i64) -> Result<()> {
+        self.limiter.acquire().await;
+        self.client
+            .execute(&format!("window.scrollBy(0,{y});"), vec![])
+            .await?;
+        let mut attempt = 0;
+        while self.is_rate_limited(attempt).await? {
+            self.limiter.acquire().await;
+            // Timelines show a retry button where the posts failed to load,
+            // which keeps the scroll position, unlike reloading
+            match self
+                .client
+                .find(Locator::XPath(
+                    "//div[@role='button'][.//span[text()='Retry']]",
+                ))
+                .await
+            {
+                Ok(e) => e.click().await?,
+                Err(CmdError::NoSuchElement(_)) => self.client.refresh().await?,
+                Err(e) => return Err(e.into()),
+            }
+            attempt += 1;
+        }
+        Ok(())
+    }
+
+    /// Waits for the page to load, and backs off if it shows that we are
+    /// being rate limited. Errors once `attempt` reaches the retry limit.
+    async fn is_rate_limited(&self, attempt: usize) -> Result<bool> {
+        wait::for_network_idle(&self.client).await?;
+        let src = self.client.source().await?;
+        let Some(state) = rate_limit::limited_state(&src) else {
+            return Ok(false);
+        };
+        if attempt >= self.limiter.max_retries() {
+            return Err(RateLimited {
+                state,
+                retries: attempt,
+            }
+            .into());
+        }
+        self.limiter.back_off(attempt).await;
+        Ok(true)
+    }
+
     pub async fn close(mut self) -> Result<()> {
-        let res = self.client.close().await.map_err(|e| e.into());
-        let mut lock = self.pool.lock().await;
-        let val = unsafe { ManuallyDrop::take(&mut self.val) };
-        lock.push(val);
+        let lease = self.lease.take();
+        let res = self.client.clone().close().await.map_err(|e| e.into());
+        drop(lease);
         res
     }
 }
 
-impl<'a> Deref for WrappedClient<'a> {
+impl Drop for WrappedClient {
+    fn drop(&mut self) {
+        let Some(mut lease) = self.lease.take() else {
+            return;
+        };
+        let url = lease.driver().url().clone();
+        let client = self.client.clone();
+        match Handle::try_current() {
+            Ok(handle) => {
+                debug!("Closing dropped client at {url} in the background");
+                handle.spawn(async move {
+                    if let Err(e) = client.close().await {
+                        warn!("Failed closing dropped client at {url}: {e}");
+                    }
+                    drop(lease);
+                });
+            }
+            Err(_) => {
+                warn!("No runtime to close dropped client at {url}, restarting its driver");
+                lease.driver().mark_stale();
+            }
+        }
+    }
+}
+
+impl Deref for WrappedClient {
     type Target = Client;
 
     fn deref(&self) -> &Self::Target {
         &self.client
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// A pool of remote drivers that are never connected to, and so are never
+    /// healthy.
+    async fn remote_pool(size: usize, health_check_interval: u64) -> DriverPool {
+        pool_of(
+            vec!["http://localhost:9".to_owned(); size],
+            health_check_interval,
+        )
+        .await
+    }
+
+    async fn pool_of(remote_urls: Vec<String>, health_check_interval: u64) -> DriverPool {
+        let config = DriverConfig {
+            backend: DriverBackend::Remote,
+            driver_count: remote_urls.len(),
+            base_port: 0,
+            remote_urls,
+            remote_browser: Some(Browser::Firefox),
+            ready_timeout: 1,
+            health_check_interval,
+            browser: BrowserConfig::default(),
+        };
+        let rate_limit_config = RateLimitConfig {
+            requests_per_minute: 60,
+            guest_requests_per_minute: 60,
+            burst: 1,
+            backoff_base: 1,
+            backoff_max: 1,
+            max_retries: 0,
+        };
+        DriverPool::new(&config, &rate_limit_config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn leases_are_returned_on_errors_and_panics() {
+        let pool = remote_pool(2, 3600).await;
+        assert_eq!(pool.available(), 2);
+
+        fn fail_with(_lease: DriverLease) -> Result<()> {
+            bail!("Failed while holding the driver")
+        }
+        let lease = DriverLease::take(&pool.pool).unwrap();
+        assert_eq!(pool.available(), 1);
+        assert!(fail_with(lease).is_err());
+        assert_eq!(pool.available(), 2);
+
+        let drivers = Arc::clone(&pool.pool);
+        let task = tokio::spawn(async move {
+            let _lease = DriverLease::take(&drivers).unwrap();
+            panic!("Panicked while holding the driver");
+        });
+        assert!(task.await.unwrap_err().is_panic());
+        assert_eq!(pool.available(), 2);
+
+        let a = DriverLease::take(&pool.pool).unwrap();
+        let b = DriverLease::take(&pool.pool).unwrap();
+        assert!(DriverLease::take(&pool.pool).is_none());
+        drop((a, b));
+        assert_eq!(pool.available(), 2);
+        pool.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn drivers_that_fail_to_restart_are_removed() {
+        let pool = remote_pool(2, 0).await;
+        drop(DriverLease::take(&pool.pool).unwrap());
+        let start = tokio::time::Instant::now();
+        while pool.pool.count() == 2 && start.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(pool.pool.count(), 1);
+        assert_eq!(pool.available(), 1);
+        pool.close().await.unwrap();
+    }
+
+    /// Guest clients don't use any of the site's settings.
+    fn guest_config() -> TwitterConfig {
+        toml::from_str(
+            r#"
+            auth_cache_fname = "auth.json"
+            css_classes = {}
+            xpaths = {}
+            "#,
+        )
+        .unwrap()
+    }
+
+    /// A WebDriver endpoint that is always ready, and hands out a session
+    /// that does nothing. Counts how many sessions were closed.
+    async fn fake_driver() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let closed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&closed);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let counter = Arc::clone(&counter);
+                tokio::spawn(async move {
+                    let mut request = vec![];
+                    let mut buf = [0; 1024];
+                    let head_len = loop {
+                        if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break i + 4;
+                        }
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                    };
+                    let head = String::from_utf8_lossy(&request[..head_len]).to_lowercase();
+                    let body_len = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .map_or(0, |l| l.trim().parse().unwrap());
+                    while request.len() < head_len + body_len {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let body = match head.split_once(' ') {
+                        Some(("get", path)) if path.starts_with("/status ") => {
+                            r#"{"value":{"ready":true,"message":""}}"#
+                        }
+                        Some(("post", path)) if path.starts_with("/session ") => {
+                            r#"{"value":{"sessionId":"fake","capabilities":{}}}"#
+                        }
+                        Some(("delete", _)) => {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            r#"{"value":null}"#
+                        }
+                        _ => r#"{"value":null}"#,
+                    };
+                    let head = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=utf-8\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    stream.write_all(head.as_bytes()).await.unwrap();
+                    stream.write_all(body.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        (url, closed)
+    }
+
+    #[tokio::test]
+    async fn dropped_clients_close_their_session() {
+        let (url, closed) = fake_driver().await;
+        let pool = pool_of(vec![url], 3600).await;
+        let client = pool
+            .get_client(&guest_config(), ClientAuth::Guest)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pool.available(), 0);
+        drop(client);
+        let start = tokio::time::Instant::now();
+        while pool.available() == 0 && start.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(pool.available(), 1);
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+        pool.close().await.unwrap();
+    }
+
+    #[test]
+    fn clients_dropped_outside_a_runtime_restart_their_driver() {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (pool, client) = rt.block_on(async {
+            let (url, _) = fake_driver().await;
+            let pool = pool_of(vec![url], 3600).await;
+            let client = pool
+                .get_client(&guest_config(), ClientAuth::Guest)
+                .await
+                .unwrap()
+                .unwrap();
+            (pool, client)
+        });
+        drop(client);
+        assert_eq!(pool.available(), 1);
+
+        rt.block_on(async {
+            let mut lease = DriverLease::take(&pool.pool).unwrap();
+            assert!(!lease.driver().is_healthy().await);
+            drop(lease);
+            // Remote drivers are restarted by waiting for them to be ready
+            let client = pool
+                .get_client(&guest_config(), ClientAuth::Guest)
+                .await
+                .unwrap()
+                .unwrap();
+            client.close().await.unwrap();
+            pool.close().await.unwrap();
+        });
+    }
+}
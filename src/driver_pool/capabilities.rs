@@ -0,0 +1,125 @@
+use color_eyre::eyre::{bail, Context, Result};
+use fantoccini::wd::Capabilities;
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use super::driver::Driver;
+use crate::config::{Browser, BrowserConfig};
+
+/// Builds the capabilities for a new session on `driver`.
+pub fn capabilities(driver: &Driver, config: &BrowserConfig) -> Result<Capabilities> {
+    let mut caps = Capabilities::new();
+    let profile_dir = match &config.profile_dir {
+        // Browsers lock their profile while running, so each driver needs its
+        // own
+        Some(dir) => {
+            let dir = format!("{dir}/{}", driver.id());
+            std::fs::create_dir_all(&dir)
+                .wrap_err_with(|| format!("Failed creating profile directory {dir}"))?;
+            Some(dir)
+        }
+        None => None,
+    };
+
+    match driver.browser() {
+        Browser::Firefox => {
+            let mut args = vec![];
+            let mut prefs = Map::new();
+            prefs.insert("javascript.enabled".into(), json!(true));
+            if config.headless {
+                args.push("-headless".to_owned());
+            }
+            if let Some((width, height)) = config.window_size {
+                args.push(format!("--width={width}"));
+                args.push(format!("--height={height}"));
+            }
+            if let Some(dir) = profile_dir {
+                args.push("-profile".to_owned());
+                args.push(dir);
+            }
+            if let Some(user_agent) = &config.user_agent {
+                prefs.insert("general.useragent.override".into(), json!(user_agent));
+            }
+            if let Some(locale) = &config.locale {
+                prefs.insert("intl.accept_languages".into(), json!(locale));
+                prefs.insert("intl.locale.requested".into(), json!(locale));
+            }
+
+            caps.insert("browserName".into(), json!("firefox"));
+            caps.insert(
+                "moz:firefoxOptions".into(),
+                json!({
+                    "args": args,
+                    "prefs": prefs,
+                }),
+            );
+        }
+        Browser::Chrome => {
+            let mut args = vec![];
+            let mut prefs = Map::new();
+            prefs.insert(
+                "profile.managed_default_content_settings.javascript".into(),
+                json!(1),
+            );
+            if config.headless {
+                args.push("--headless=new".to_owned());
+            }
+            if let Some((width, height)) = config.window_size {
+                args.push(format!("--window-size={width},{height}"));
+            }
+            if let Some(dir) = profile_dir {
+                args.push(format!("--user-data-dir={dir}"));
+            }
+            if let Some(user_agent) = &config.user_agent {
+                args.push(format!("--user-agent={user_agent}"));
+            }
+            if let Some(locale) = &config.locale {
+                args.push(format!("--lang={locale}"));
+                prefs.insert("intl.accept_languages".into(), json!(locale));
+            }
+
+            caps.insert("browserName".into(), json!("chrome"));
+            caps.insert(
+                "goog:chromeOptions".into(),
+                json!({
+                    "args": args,
+                    "prefs": prefs,
+                }),
+            );
+        }
+    }
+
+    if !config.proxies.is_empty() {
+        let proxy = &config.proxies[driver.id() % config.proxies.len()];
+        caps.insert("proxy".into(), proxy_capability(proxy)?);
+    }
+
+    Ok(caps)
+}
+
+/// Turns a proxy URL like `socks5://host:port` into the WebDriver `proxy`
+/// capability.
+fn proxy_capability(proxy: &str) -> Result<Value> {
+    let url = Url::parse(proxy).wrap_err_with(|| format!("Invalid proxy URL {proxy}"))?;
+    let Some(host) = url.host_str() else {
+        bail!("Proxy URL {proxy} has no host");
+    };
+    let Some(port) = url.port_or_known_default() else {
+        bail!("Proxy URL {proxy} has no port");
+    };
+    let address = format!("{host}:{port}");
+
+    Ok(match url.scheme() {
+        "http" | "https" => json!({
+            "proxyType": "manual",
+            "httpProxy": address,
+            "sslProxy": address,
+        }),
+        "socks4" | "socks5" => json!({
+            "proxyType": "manual",
+            "socksProxy": address,
+            "socksVersion": if url.scheme() == "socks4" { 4 } else { 5 },
+        }),
+        scheme => bail!("Unsupported proxy scheme `{scheme}`"),
+    })
+}
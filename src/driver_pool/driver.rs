@@ -0,0 +1,261 @@
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use std::{process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    process::{Child, Command},
+    time::{timeout, Instant},
+};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::config::Browser;
+
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum DriverKind {
+    /// A driver process spawned by us, listening on localhost
+    Local { child: Child, port: usize },
+    /// A WebDriver endpoint managed by something else, i.e. a Selenium grid
+    Remote,
+}
+
+/// A WebDriver endpoint, for a specific browser.
+pub struct Driver {
+    /// Position of the driver in the pool, stable across restarts
+    id: usize,
+    kind: DriverKind,
+    browser: Browser,
+    url: Url,
+    /// When the driver was last seen working
+    last_checked: Instant,
+    /// Whether the driver may still hold a session that couldn't be closed
+    stale: bool,
+}
+
+impl Driver {
+    /// Spawns a driver for `browser`, and waits until it reports being ready.
+    pub async fn spawn(
+        id: usize,
+        browser: Browser,
+        port: usize,
+        ready_timeout: Duration,
+    ) -> Result<Self> {
+        let program = browser.driver_program();
+        let mut command = Command::new(program);
+        match browser {
+            Browser::Firefox => command.arg("-p").arg(format!("{port}")),
+            Browser::Chrome => command.arg(format!("--port={port}")),
+        };
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .wrap_err_with(|| format!("Failed spawning {program} process"))?;
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_logs(program, port, stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_logs(program, port, stderr));
+        }
+
+        let url = Url::parse(&format!("http://localhost:{port}")).unwrap();
+        let mut driver = Driver {
+            id,
+            kind: DriverKind::Local { child, port },
+            browser,
+            url,
+            last_checked: Instant::now(),
+            stale: false,
+        };
+        if let Err(e) = driver.wait_until_ready(ready_timeout).await {
+            driver.kill().await?;
+            return Err(e);
+        }
+        debug!("Driver at {} is ready", driver.url);
+        Ok(driver)
+    }
+
+    pub fn remote(id: usize, browser: Browser, url: &str) -> Result<Self> {
+        let url = Url::parse(url).wrap_err_with(|| format!("Invalid remote driver URL {url}"))?;
+        if url.scheme() != "http" {
+            warn!("Health checks are only supported over HTTP, {url} won't be checked");
+        }
+        Ok(Driver {
+            id,
+            kind: DriverKind::Remote,
+            browser,
+            url,
+            last_checked: Instant::now(),
+            stale: false,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn browser(&self) -> Browser {
+        self.browser
+    }
+
+    pub fn last_checked(&self) -> Instant {
+        self.last_checked
+    }
+
+    /// Marks the driver as still holding a session, so that it's restarted
+    /// before being used again.
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    async fn wait_until_ready(&mut self, ready_timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            if let DriverKind::Local { child, .. } = &mut self.kind {
+                if let Some(status) = child.try_wait()? {
+                    bail!("Driver at {} exited with {status}", self.url);
+                }
+            }
+            if let Ok(true) = get_ready(&self.url).await {
+                return Ok(());
+            }
+            if start.elapsed() > ready_timeout {
+                bail!("Driver at {} did not become ready in time", self.url);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Whether the driver is running, and ready to accept a new session.
+    pub async fn is_healthy(&mut self) -> bool {
+        let healthy = self.check_health().await;
+        if healthy {
+            self.last_checked = Instant::now();
+        }
+        healthy
+    }
+
+    async fn check_health(&mut self) -> bool {
+        if self.stale {
+            warn!("Driver at {} may still hold an old session", self.url);
+            return false;
+        }
+        match &mut self.kind {
+            DriverKind::Local { child, .. } => match child.try_wait() {
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    warn!("Driver at {} exited with {status}", self.url);
+                    return false;
+                }
+                Err(e) => {
+                    warn!("Failed checking driver at {}: {e}", self.url);
+                    return false;
+                }
+            },
+            DriverKind::Remote if self.url.scheme() != "http" => return true,
+            DriverKind::Remote => {}
+        }
+        match get_ready(&self.url).await {
+            Ok(true) => true,
+            Ok(false) => {
+                warn!("Driver at {} is not ready", self.url);
+                false
+            }
+            Err(e) => {
+                warn!("Driver at {} did not respond: {e:#}", self.url);
+                false
+            }
+        }
+    }
+
+    /// Kills this driver and spawns a new one in its place. Remote drivers
+    /// can't be restarted, so this just waits for them to be ready again.
+    pub async fn restart(&mut self, ready_timeout: Duration) -> Result<()> {
+        info!("Restarting driver at {}", self.url);
+        match self.kind {
+            DriverKind::Local { port, .. } => {
+                self.kill().await?;
+                *self = Driver::spawn(self.id, self.browser, port, ready_timeout).await?;
+            }
+            DriverKind::Remote => {
+                self.wait_until_ready(ready_timeout).await?;
+                self.stale = false;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn kill(&mut self) -> Result<()> {
+        if let DriverKind::Local { child, .. } = &mut self.kind {
+            if child.try_wait()?.is_none() {
+                child.start_kill()?;
+            }
+            child.wait().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Queries the WebDriver `/status` endpoint, and returns its `ready` value.
+async fn get_ready(url: &Url) -> Result<bool> {
+    let host = url.host_str().ok_or(eyre!("Driver URL has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or(eyre!("Driver URL has no port"))?;
+    let path = format!("{}/status", url.path().trim_end_matches('/'));
+
+    let res = timeout(STATUS_TIMEOUT, async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        let req =
+            format!("GET {path} HTTP/1.1\r\nHost: {host}:{port}\r\nConnection: close\r\n\r\n");
+        stream.write_all(req.as_bytes()).await?;
+        let mut res = vec![];
+        stream.read_to_end(&mut res).await?;
+        Ok::<_, std::io::Error>(res)
+    })
+    .await
+    .wrap_err("Timed out querying status")?
+    .wrap_err("Failed querying status")?;
+
+    let res = String::from_utf8_lossy(&res);
+    let (_, body) = res
+        .split_once("\r\n\r\n")
+        .ok_or(eyre!("Malformed status response"))?;
+    let status: serde_json::Value =
+        serde_json::from_str(body).wrap_err("Failed parsing status response")?;
+    status
+        .pointer("/value/ready")
+        .and_then(|v| v.as_bool())
+        .ok_or(eyre!("Status response had no `ready` field"))
+}
+
+fn log_level(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix('[') {
+        // chromedriver: [<timestamp>][<level>]: <message>
+        rest.split_once("][")
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(level, _)| level)
+    } else {
+        // geckodriver: <timestamp>\t<module>\t<level>\t<message>
+        line.split('\t').nth(2)
+    }
+}
+
+/// Forwards driver output to tracing, using the level the driver logged with.
+async fn forward_logs(program: &'static str, port: usize, output: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match log_level(&line) {
+            Some("FATAL" | "ERROR" | "SEVERE") => error!(target: "driver", program, port, "{line}"),
+            Some("WARN" | "WARNING") => warn!(target: "driver", program, port, "{line}"),
+            _ => debug!(target: "driver", program, port, "{line}"),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result};
+use std::{fmt::Write, path::Path};
+
+use crate::utils::escape;
+
+pub mod posts;
+pub mod status;
+
+/// An RSS 2.0 channel.
+pub struct Feed {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub items: Vec<FeedItem>,
+}
+
+pub struct FeedItem {
+    pub title: String,
+    pub link: String,
+    /// Rendered as HTML by most readers
+    pub description: String,
+    /// Stable identifier, so readers don't show an item twice
+    pub guid: String,
+    pub published: DateTime<Utc>,
+    /// Attached media, as Media RSS `media:content`
+    pub media: Vec<MediaContent>,
+}
+
+pub struct MediaContent {
+    pub url: String,
+    /// `image` or `video`
+    pub medium: &'static str,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub description: Option<String>,
+}
+
+impl Feed {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        xml.push('\n');
+        xml.push_str(r#"<rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/"><channel>"#);
+        xml.push('\n');
+        element(&mut xml, "title", &self.title);
+        element(&mut xml, "link", &self.link);
+        element(&mut xml, "description", &self.description);
+        if let Some(latest) = self.items.iter().map(|i| i.published).max() {
+            element(&mut xml, "lastBuildDate", &latest.to_rfc2822());
+        }
+        for item in &self.items {
+            xml.push_str("<item>\n");
+            element(&mut xml, "title", &item.title);
+            element(&mut xml, "link", &item.link);
+            element(&mut xml, "description", &item.description);
+            let _ = writeln!(
+                xml,
+                r#"<guid isPermaLink="false">{}</guid>"#,
+                escape(&item.guid)
+            );
+            element(&mut xml, "pubDate", &item.published.to_rfc2822());
+            for media in &item.media {
+                media_content(&mut xml, media);
+            }
+            xml.push_str("</item>\n");
+        }
+        xml.push_str("</channel></rss>\n");
+        xml
+    }
+
+    /// Writes the feed to `path`, creating its parent directory if needed.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Failed creating {}", dir.display()))?;
+        }
+        std::fs::write(path, self.to_xml())
+            .wrap_err_with(|| format!("Failed writing feed to {}", path.display()))
+    }
+}
+
+fn media_content(xml: &mut String, media: &MediaContent) {
+    let _ = write!(
+        xml,
+        r#"<media:content url="{}" medium="{}""#,
+        escape(&media.url),
+        media.medium
+    );
+    if let (Some(width), Some(height)) = (media.width, media.height) {
+        let _ = write!(xml, r#" width="{width}" height="{height}""#);
+    }
+    match &media.description {
+        Some(description) => {
+            let _ = writeln!(
+                xml,
+                r#"><media:description type="plain">{}</media:description></media:content>"#,
+                escape(description)
+            );
+        }
+        None => xml.push_str("/>\n"),
+    }
+}
+
+fn element(xml: &mut String, name: &str, text: &str) {
+    let _ = writeln!(xml, "<{name}>{}</{name}>", escape(text));
+}
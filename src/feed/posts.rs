@@ -0,0 +1,415 @@
+use color_eyre::eyre::{Context, Result};
+use indexmap::IndexMap;
+use std::{collections::HashMap, fmt::Write, path::Path};
+
+use super::{Feed, FeedItem, MediaContent};
+use crate::config::Config;
+use crate::db::{Db, Source, StoredPost};
+use crate::fetch::post::{LinkCard, MediaKind, Poll, Timeline};
+use crate::utils::{escape, get_user_link};
+
+fn render_poll(html: &mut String, poll: &Poll) {
+    html.push_str("<ul>");
+    for option in &poll.options {
+        let _ = write!(html, "<li>{}", escape(&option.label));
+        if let Some(percent) = option.percent {
+            let _ = write!(html, ": {percent}%");
+            if let Some(votes) = poll.votes.and_then(|total| option.votes(total)) {
+                let _ = write!(html, " ({votes} votes)");
+            }
+        }
+        html.push_str("</li>");
+    }
+    html.push_str("</ul><p><small>");
+    if let Some(votes) = poll.votes {
+        let _ = write!(html, "{votes} votes. ");
+    }
+    match (poll.ended, poll.ends_at) {
+        (true, _) => html.push_str("Final results"),
+        (false, Some(ends_at)) => {
+            let _ = write!(html, "Ends {}", ends_at.format("%Y-%m-%d %H:%M UTC"));
+        }
+        (false, None) => html.push_str("Still open"),
+    }
+    html.push_str("</small></p>");
+}
+
+fn render_card(html: &mut String, card: &LinkCard) {
+    let url = escape(&card.url);
+    html.push_str("<blockquote>");
+    if let Some(thumbnail) = &card.thumbnail_url {
+        let _ = write!(
+            html,
+            r#"<p><a href="{url}"><img src="{}" alt=""></a></p>"#,
+            escape(thumbnail)
+        );
+    }
+    let title = card.title.as_deref().unwrap_or(&card.url);
+    let _ = write!(
+        html,
+        r#"<p><a href="{url}"><strong>{}</strong></a></p>"#,
+        escape(title)
+    );
+    if let Some(description) = &card.description {
+        let _ = write!(html, "<p>{}</p>", escape(description));
+    }
+    if let Some(domain) = &card.domain {
+        let _ = write!(html, "<p><small>{}</small></p>", escape(domain));
+    }
+    html.push_str("</blockquote>");
+}
+
+/// Renders `post` as the HTML description of a feed item.
+fn render_post(post: &StoredPost) -> String {
+    let mut html = String::new();
+    if post.entities.0.is_empty() {
+        let _ = write!(html, "<p>{}</p>", escape(&post.text).replace('\n', "<br>"));
+    } else {
+        let _ = write!(html, "<p>{}</p>", post.entities.html());
+    }
+    render_media(&mut html, post);
+    if let Some(poll) = &post.poll {
+        render_poll(&mut html, poll);
+    }
+    if let Some(card) = &post.card {
+        render_card(&mut html, card);
+    }
+    if let Some(quote) = &post.quote {
+        let _ = write!(
+            html,
+            r#"<blockquote><p><a href="{}">@{}</a>:</p>{}</blockquote>"#,
+            escape(&quote.link),
+            escape(&quote.username),
+            render_post(quote)
+        );
+    } else if let Some(quote_of) = &post.quote_of {
+        // Not archived, or quoted from inside a quote
+        let _ = write!(
+            html,
+            r#"<blockquote><p><a href="https://twitter.com/i/status/{}">Quoted post</a></p></blockquote>"#,
+            escape(quote_of)
+        );
+    }
+    if let Some(note) = &post.community_note {
+        let _ = write!(
+            html,
+            "<aside><p><strong>Readers added context:</strong> {}</p></aside>",
+            escape(&note.text)
+        );
+    }
+    // The last revision is the current text
+    if let Some((_, earlier)) = post.revisions.split_last() {
+        if !earlier.is_empty() {
+            html.push_str("<p><small>Edited. Earlier versions: ");
+            let links = earlier
+                .iter()
+                .map(|r| {
+                    format!(
+                        r#"<a href="{}">{}</a>"#,
+                        escape(&r.link),
+                        r.posted_at.format("%Y-%m-%d %H:%M")
+                    )
+                })
+                .collect::<Vec<_>>();
+            html.push_str(&links.join(", "));
+            html.push_str("</small></p>");
+        }
+    }
+    html
+}
+
+/// Links the screenshot of `post`, if it has one and the media store is
+/// served somewhere.
+fn render_screenshot(html: &mut String, post: &StoredPost, config: &Config) {
+    let (Some(path), Some(base_url)) = (&post.screenshot_path, &config.feed_config.media_base_url)
+    else {
+        return;
+    };
+    let media_dir = Path::new(&config.storage_config.media_dir);
+    let Ok(relative) = Path::new(path).strip_prefix(media_dir) else {
+        return;
+    };
+    let relative = relative
+        .iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let _ = write!(
+        html,
+        r#"<p><small><a href="{}/{}">Screenshot</a></small></p>"#,
+        escape(base_url.trim_end_matches('/')),
+        escape(&relative)
+    );
+}
+
+fn post_item(post: &StoredPost, config: &Config) -> FeedItem {
+    let mut description = render_post(post);
+    render_screenshot(&mut description, post, config);
+    FeedItem {
+        title: title(post),
+        link: post.link.clone(),
+        description,
+        guid: post.id.clone(),
+        published: post.posted_at,
+        media: media_contents(post),
+    }
+}
+
+fn media_contents(post: &StoredPost) -> Vec<MediaContent> {
+    post.media
+        .iter()
+        .filter_map(|m| {
+            let (url, medium) = match (m.kind, &m.url) {
+                (MediaKind::Photo, Some(url)) => (url, "image"),
+                (_, Some(url)) => (url, "video"),
+                // Videos without a single file to point to are only shown
+                // with their preview
+                (_, None) => (m.preview_url.as_ref()?, "image"),
+            };
+            Some(MediaContent {
+                url: url.clone(),
+                medium,
+                width: m.width,
+                height: m.height,
+                description: m.alt.clone(),
+            })
+        })
+        .collect()
+}
+
+fn render_media(html: &mut String, post: &StoredPost) {
+    for m in &post.media {
+        let alt = escape(m.alt.as_deref().unwrap_or_default());
+        let size = match (m.width, m.height) {
+            (Some(width), Some(height)) => format!(r#" width="{width}" height="{height}""#),
+            _ => String::new(),
+        };
+        match (m.kind, &m.url, &m.preview_url) {
+            (MediaKind::Photo, Some(url), _) => {
+                let _ = write!(
+                    html,
+                    r#"<p><img src="{}" alt="{alt}"{size}></p>"#,
+                    escape(url)
+                );
+            }
+            (MediaKind::Gif, Some(url), poster) => {
+                let poster = poster
+                    .as_ref()
+                    .map(|p| format!(r#" poster="{}""#, escape(p)))
+                    .unwrap_or_default();
+                let _ = write!(
+                    html,
+                    r#"<p><video src="{}"{poster} title="{alt}"{size} autoplay loop muted playsinline></video></p>"#,
+                    escape(url)
+                );
+            }
+            (_, _, Some(preview)) => {
+                let _ = write!(
+                    html,
+                    r#"<p><a href="{}"><img src="{}" alt="{alt}"{size}></a></p>"#,
+                    escape(&post.link),
+                    escape(preview)
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The first line of the post, shortened to something that fits a title.
+fn title(post: &StoredPost) -> String {
+    const MAX_CHARS: usize = 80;
+    let line = post.text.lines().next().unwrap_or_default();
+    if line.trim().is_empty() {
+        // Posts with only media
+        format!("Post by @{}", post.username)
+    } else if line.chars().count() > MAX_CHARS {
+        let mut title = line.chars().take(MAX_CHARS - 1).collect::<String>();
+        title.push('…');
+        title
+    } else {
+        line.to_owned()
+    }
+}
+
+/// Groups posts that reply to another one in `posts` with it, in the order
+/// they were posted. Threads are ordered by their latest post, newest first.
+fn self_threads(posts: Vec<StoredPost>) -> Vec<Vec<StoredPost>> {
+    let parents = posts
+        .iter()
+        .map(|p| (p.id.clone(), p.in_reply_to.clone()))
+        .collect::<HashMap<_, _>>();
+    let root = |id: &str| {
+        let mut id = id.to_owned();
+        // Each step goes to an older post, so this only loops if the archive
+        // is corrupt, which the step limit guards against
+        for _ in 0..parents.len() {
+            match parents.get(&id) {
+                Some(Some(parent)) if parents.contains_key(parent) => id = parent.clone(),
+                _ => break,
+            }
+        }
+        id
+    };
+
+    let mut threads: IndexMap<String, Vec<StoredPost>> = IndexMap::new();
+    for post in posts {
+        threads.entry(root(&post.id)).or_default().push(post);
+    }
+    let mut threads = threads.into_values().collect::<Vec<_>>();
+    for thread in &mut threads {
+        thread.sort_by_key(|p| p.posted_at);
+    }
+    threads.sort_by_key(|t| std::cmp::Reverse(t.last().unwrap().posted_at));
+    threads
+}
+
+/// A single item holding every post in a self-thread.
+fn thread_item(thread: &[StoredPost], config: &Config) -> FeedItem {
+    let root = &thread[0];
+    let mut description = String::new();
+    for (i, post) in thread.iter().enumerate() {
+        if i != 0 {
+            description.push_str("<hr>");
+        }
+        let _ = write!(
+            description,
+            r#"<p><small><a href="{}">{}/{}</a></small></p>"#,
+            escape(&post.link),
+            i + 1,
+            thread.len()
+        );
+        description.push_str(&render_post(post));
+        render_screenshot(&mut description, post, config);
+    }
+    FeedItem {
+        title: title(root),
+        link: root.link.clone(),
+        description,
+        guid: root.id.clone(),
+        // So readers see the thread again once it grows
+        published: thread.last().unwrap().posted_at,
+        media: thread.iter().flat_map(media_contents).collect(),
+    }
+}
+
+async fn write_user_feed(db: &Db, config: &Config, user: &str) -> Result<()> {
+    let posts = db
+        .get_user_posts(user, config.feed_config.max_items)
+        .await?;
+    let items = if config.feed_config.collapse_threads {
+        self_threads(posts)
+            .iter()
+            .map(|thread| match thread.as_slice() {
+                [post] => post_item(post, config),
+                thread => thread_item(thread, config),
+            })
+            .collect()
+    } else {
+        posts.iter().map(|p| post_item(p, config)).collect()
+    };
+    let feed = Feed {
+        title: format!("@{user}"),
+        link: get_user_link(user),
+        description: format!("Posts from @{user}"),
+        items,
+    };
+    feed.write(format!("{}/{user}.xml", config.feed_config.output_dir))
+}
+
+/// Writes a feed with the latest posts of every user in the archive.
+pub async fn write_user_feeds(db: &Db, config: &Config) -> Result<()> {
+    for user in db.get_post_authors().await? {
+        write_user_feed(db, config, &user)
+            .await
+            .wrap_err_with(|| format!("Failed writing feed for {user}"))?;
+    }
+    Ok(())
+}
+
+/// Items for posts from many users, whose titles say who posted them.
+fn mixed_items(posts: &[StoredPost], config: &Config) -> Vec<FeedItem> {
+    posts
+        .iter()
+        .map(|post| {
+            let mut item = post_item(post, config);
+            item.title = format!("@{}: {}", post.username, item.title);
+            item
+        })
+        .collect()
+}
+
+/// Writes a feed with the latest posts liked by each user whose likes are
+/// archived.
+pub async fn write_likes_feeds(db: &Db, config: &Config) -> Result<()> {
+    for user in db.get_timeline_users(Timeline::Likes).await? {
+        let source = Source::Timeline {
+            user: &user,
+            timeline: Timeline::Likes,
+        };
+        let posts = db
+            .get_source_posts(source, config.feed_config.max_items)
+            .await?;
+        let feed = Feed {
+            title: format!("Liked by @{user}"),
+            link: format!("{}/likes", get_user_link(&user)),
+            description: format!("Posts liked by @{user}"),
+            items: mixed_items(&posts, config),
+        };
+        feed.write(format!(
+            "{}/likes/{user}.xml",
+            config.feed_config.output_dir
+        ))
+        .wrap_err_with(|| format!("Failed writing likes feed for {user}"))?;
+    }
+    Ok(())
+}
+
+/// Writes a feed with the latest posts found by each search in the config.
+pub async fn write_search_feeds(db: &Db, config: &Config) -> Result<()> {
+    for search in &config.searches {
+        let posts = db
+            .get_source_posts(
+                Source::Search { name: &search.name },
+                config.feed_config.max_items,
+            )
+            .await?;
+        let feed = Feed {
+            title: format!("Search: {}", search.name),
+            link: format!(
+                "https://twitter.com/search?q={}&f=live",
+                url::form_urlencoded::byte_serialize(search.query.as_bytes()).collect::<String>()
+            ),
+            description: format!("Posts matching {}", search.query),
+            items: mixed_items(&posts, config),
+        };
+        feed.write(format!(
+            "{}/search/{}.xml",
+            config.feed_config.output_dir, search.name
+        ))
+        .wrap_err_with(|| format!("Failed writing feed for search {}", search.name))?;
+    }
+    Ok(())
+}
+
+/// Writes a feed with the latest posts in the timeline of each list archived
+/// as a whole.
+pub async fn write_list_feeds(db: &Db, config: &Config) -> Result<()> {
+    for list in config.lists.iter().filter(|l| l.timeline) {
+        let posts = db
+            .get_source_posts(Source::List { id: &list.id }, config.feed_config.max_items)
+            .await?;
+        let feed = Feed {
+            title: format!("List: {}", list.name),
+            link: format!("https://twitter.com/i/lists/{}", list.id),
+            description: format!("Posts in the list {}", list.name),
+            items: mixed_items(&posts, config),
+        };
+        feed.write(format!(
+            "{}/list/{}.xml",
+            config.feed_config.output_dir, list.name
+        ))
+        .wrap_err_with(|| format!("Failed writing feed for list {}", list.name))?;
+    }
+    Ok(())
+}
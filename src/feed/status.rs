@@ -0,0 +1,45 @@
+use color_eyre::eyre::Result;
+
+use super::{Feed, FeedItem};
+use crate::config::Config;
+use crate::db::Db;
+use crate::fetch::users::AccountStatus;
+use crate::utils::get_user_link;
+
+/// Writes a feed with every time a followed account was suspended, deleted,
+/// protected, or came back. It's kept out of the directory of user feeds,
+/// which are named after the user.
+pub async fn write_status_feed(db: &Db, config: &Config) -> Result<()> {
+    let changes = db.get_status_changes(config.feed_config.max_items).await?;
+    let items = changes
+        .into_iter()
+        .map(|change| {
+            let user = &change.username;
+            let title = match change.new_status {
+                AccountStatus::Active => format!("@{user} is {} again", change.new_status),
+                status => format!("@{user} is now {status}"),
+            };
+            FeedItem {
+                description: format!(
+                    "@{user} went from {} to {}",
+                    change.old_status, change.new_status
+                ),
+                title,
+                link: get_user_link(user),
+                guid: format!("account-status-{}", change.id),
+                published: change.changed_at,
+                media: vec![],
+            }
+        })
+        .collect();
+    let feed = Feed {
+        title: "Account status changes".to_owned(),
+        link: "https://twitter.com".to_owned(),
+        description: "Followed accounts that were suspended, deleted or protected".to_owned(),
+        items,
+    };
+    feed.write(format!(
+        "{}/status/accounts.xml",
+        config.feed_config.output_dir
+    ))
+}
@@ -1,2 +1,5 @@
+pub mod error;
+pub mod login;
 pub mod post;
+pub mod text;
 pub mod users;
@@ -0,0 +1,169 @@
+use color_eyre::Report;
+use fantoccini::error::CmdError;
+use serde::Deserialize;
+use std::fmt;
+
+use crate::rate_limit::RateLimited;
+use crate::wait::Timeout;
+
+/// Why fetching something from the site failed.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The account doesn't exist
+    NotFound,
+    /// The account was suspended
+    Suspended,
+    /// The account only shows its posts to followers
+    Protected,
+    /// The post was deleted, or can't be seen anymore. `reason` is what the
+    /// site showed instead
+    PostUnavailable {
+        reason: String,
+    },
+    /// The site kept rate limiting us
+    RateLimited(RateLimited),
+    /// The page can't be viewed without being logged in
+    LoginRequired,
+    /// An element that should be in the page wasn't, which usually means the
+    /// site changed
+    LayoutChanged {
+        selector: String,
+    },
+    /// The page didn't load what was expected in time
+    Timeout(Timeout),
+    /// The WebDriver session failed
+    Driver(CmdError),
+    Other(Report),
+}
+
+pub type FetchResult<T> = std::result::Result<T, FetchError>;
+
+/// The category of a [`FetchError`], used to decide what to retry, and stored
+/// alongside users that failed.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchErrorKind {
+    NotFound,
+    Suspended,
+    Protected,
+    PostUnavailable,
+    RateLimited,
+    LoginRequired,
+    LayoutChanged,
+    Timeout,
+    Driver,
+    Other,
+}
+
+impl FetchErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetchErrorKind::NotFound => "not_found",
+            FetchErrorKind::Suspended => "suspended",
+            FetchErrorKind::Protected => "protected",
+            FetchErrorKind::PostUnavailable => "post_unavailable",
+            FetchErrorKind::RateLimited => "rate_limited",
+            FetchErrorKind::LoginRequired => "login_required",
+            FetchErrorKind::LayoutChanged => "layout_changed",
+            FetchErrorKind::Timeout => "timeout",
+            FetchErrorKind::Driver => "driver",
+            FetchErrorKind::Other => "other",
+        }
+    }
+}
+
+impl FetchError {
+    pub fn layout_changed(selector: impl Into<String>) -> Self {
+        FetchError::LayoutChanged {
+            selector: selector.into(),
+        }
+    }
+
+    pub fn kind(&self) -> FetchErrorKind {
+        match self {
+            FetchError::NotFound => FetchErrorKind::NotFound,
+            FetchError::Suspended => FetchErrorKind::Suspended,
+            FetchError::Protected => FetchErrorKind::Protected,
+            FetchError::PostUnavailable { .. } => FetchErrorKind::PostUnavailable,
+            FetchError::RateLimited(_) => FetchErrorKind::RateLimited,
+            FetchError::LoginRequired => FetchErrorKind::LoginRequired,
+            FetchError::LayoutChanged { .. } => FetchErrorKind::LayoutChanged,
+            FetchError::Timeout(_) => FetchErrorKind::Timeout,
+            FetchError::Driver(_) => FetchErrorKind::Driver,
+            // Errors that had context added can still be categorized by what
+            // caused them
+            FetchError::Other(e) => e
+                .chain()
+                .find_map(|cause| {
+                    if let Some(e) = cause.downcast_ref::<FetchError>() {
+                        Some(e.kind())
+                    } else if cause.is::<RateLimited>() {
+                        Some(FetchErrorKind::RateLimited)
+                    } else if cause.is::<Timeout>() {
+                        Some(FetchErrorKind::Timeout)
+                    } else if cause.is::<CmdError>() {
+                        Some(FetchErrorKind::Driver)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(FetchErrorKind::Other),
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::NotFound => write!(f, "Account doesn't exist"),
+            FetchError::Suspended => write!(f, "Account is suspended"),
+            FetchError::Protected => write!(f, "Account is protected"),
+            FetchError::PostUnavailable { reason } => write!(f, "Post is unavailable: {reason}"),
+            FetchError::RateLimited(e) => write!(f, "{e}"),
+            FetchError::LoginRequired => write!(f, "Content requires being logged in"),
+            FetchError::LayoutChanged { selector } => write!(
+                f,
+                "Could not find `{selector}`, the page layout probably changed"
+            ),
+            FetchError::Timeout(e) => write!(f, "{e}"),
+            FetchError::Driver(e) => write!(f, "WebDriver error: {e}"),
+            FetchError::Other(e) => write!(f, "{e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Driver(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CmdError> for FetchError {
+    fn from(e: CmdError) -> Self {
+        FetchError::Driver(e)
+    }
+}
+
+impl From<Report> for FetchError {
+    fn from(e: Report) -> Self {
+        let e = match e.downcast::<FetchError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<CmdError>() {
+            Ok(e) => return FetchError::Driver(e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<RateLimited>() {
+            Ok(e) => return FetchError::RateLimited(e),
+            Err(e) => e,
+        };
+        match e.downcast::<Timeout>() {
+            Ok(e) => FetchError::Timeout(e),
+            Err(e) => FetchError::Other(e),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use fantoccini::Client;
+use tracing::debug;
+
+use super::error::{FetchError, FetchResult};
+
+fn is_login_wall(url: &str, src: &str) -> bool {
+    // Logged out users get redirected to the login flow for most pages, but
+    // some only show a modal on top of the page
+    url.contains("/i/flow/login")
+        || url.ends_with("/login")
+        || (src.contains("data-testid=\"sheetDialog\"") && src.contains("Sign in to X"))
+}
+
+/// Errors with [`FetchError::LoginRequired`] if the current page is asking to
+/// log in.
+pub async fn check_login_wall(c: &Client) -> FetchResult<()> {
+    let url = c.current_url().await?;
+    let src = c.source().await?;
+    if is_login_wall(url.as_str(), &src) {
+        debug!("Hit login wall at {url}");
+        return Err(FetchError::LoginRequired);
+    }
+    Ok(())
+}
@@ -1,18 +1,164 @@
+use chrono::{DateTime, Utc};
 use color_eyre::{
-    eyre::{bail, eyre},
-    Result,
+    eyre::{eyre, Context},
+    Report,
 };
-use fantoccini::Client;
+use fantoccini::Locator;
 use regex::Regex;
-use scraper::{Html, Selector};
-use tracing::{debug, info};
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, info, warn};
 
+use super::error::{FetchError, FetchErrorKind, FetchResult};
+use super::login::check_login_wall;
+use super::text::{expanded_url, is_short_url, RichText};
+use super::users::is_protected;
 use crate::config::Config;
-use crate::utils::{get_post_full_link, sleep_secs};
+use crate::driver_pool::WrappedClient;
+use crate::utils::get_post_full_link;
+use crate::wait;
 
-async fn get_recent_posts_from_user(c: &Client, user_id: &str, config: &Config) -> Result<Vec<()>> {
-    c.goto(&format!("https://twitter.com/{user_id}")).await?;
-    sleep_secs(4).await;
+mod attachments;
+
+use attachments::{
+    in_quote, is_quote, parse_community_note, parse_link_card, parse_media, parse_poll,
+};
+pub use attachments::{CommunityNote, LinkCard, Media, MediaKind, Poll};
+
+/// A post, as shown on its status page.
+#[derive(Debug, Clone)]
+pub struct FetchedPost {
+    pub id: String,
+    pub username: String,
+    pub link: String,
+    /// The text without any formatting, for searching and titles
+    pub text: String,
+    pub entities: RichText,
+    pub posted_at: DateTime<Utc>,
+    /// Id of the post this one replies to
+    pub in_reply_to: Option<String>,
+    /// Link to the post this one quotes
+    pub quote_link: Option<String>,
+    pub poll: Option<Poll>,
+    pub card: Option<LinkCard>,
+    pub community_note: Option<CommunityNote>,
+    pub media: Vec<Media>,
+    /// Every version of the post, oldest first. Empty if it was never edited
+    pub revisions: Vec<PostRevision>,
+    /// PNG of the post as shown on its status page, if one was taken
+    pub screenshot: Option<Vec<u8>>,
+}
+
+impl FetchedPost {
+    /// Id of the post this one quotes.
+    pub fn quote_id(&self) -> Option<&str> {
+        let (_, id) = split_status_link(self.quote_link.as_deref()?)?;
+        Some(id)
+    }
+}
+
+/// A version of an edited post. Each one has its own status.
+#[derive(Debug, Clone)]
+pub struct PostRevision {
+    pub id: String,
+    pub link: String,
+    pub text: String,
+    pub entities: RichText,
+    pub posted_at: DateTime<Utc>,
+}
+
+/// What's already archived of the posts seen on a timeline, that doesn't need
+/// fetching again.
+#[derive(Debug, Default)]
+pub struct Archived {
+    /// Ids of the posts that were screenshotted
+    pub screenshotted: HashSet<String>,
+    /// Every version of the edited posts, by post id
+    pub revisions: HashMap<String, Vec<PostRevision>>,
+}
+
+/// A tab of a user's profile that posts are archived from.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Timeline {
+    /// The default tab, with the user's posts and reposts
+    Posts,
+    Replies,
+    Media,
+    /// Only visible for some accounts
+    Likes,
+}
+
+impl Timeline {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Timeline::Posts => "posts",
+            Timeline::Replies => "replies",
+            Timeline::Media => "media",
+            Timeline::Likes => "likes",
+        }
+    }
+
+    fn path(&self) -> &'static str {
+        match self {
+            Timeline::Posts => "",
+            Timeline::Replies => "/with_replies",
+            Timeline::Media => "/media",
+            Timeline::Likes => "/likes",
+        }
+    }
+
+    /// Whether the tab shows posts by other users, that are archived too.
+    fn has_others_posts(&self) -> bool {
+        *self == Timeline::Likes
+    }
+}
+
+/// True once a post, or an item of the media grid, has been rendered.
+const TIMELINE_RENDERED: &str =
+    "return document.querySelector('article, main li a[href*=\"/status/\"]') !== null;";
+
+/// Links to the posts in the media grid, which links to each photo or video
+/// instead of the post.
+fn media_grid_links(doc: &Html) -> Vec<String> {
+    let re = Regex::new(r"^(/\w+/status/\d+)/(?:photo|video)/\d+$").unwrap();
+    let anchor_selector = &Selector::parse("main li a[href]").unwrap();
+    doc.select(anchor_selector)
+        .filter_map(|a| re.captures(a.value().attr("href")?))
+        .map(|c| c[1].to_owned())
+        .collect()
+}
+
+/// Fetches the latest posts on the `timeline` tab of `user_id`. Posts are
+/// screenshotted if enabled, unless they're already `archived` with one. Tabs
+/// that can't be seen have no posts.
+pub async fn get_recent_posts_from_user(
+    c: &WrappedClient,
+    user_id: &str,
+    timeline: Timeline,
+    config: &Config,
+    archived: &Archived,
+) -> FetchResult<Vec<FetchedPost>> {
+    c.goto(&format!("https://twitter.com/{user_id}{}", timeline.path()))
+        .await?;
+    check_login_wall(c).await?;
+    let src = c.source().await?;
+    if is_protected(&src) {
+        return Err(FetchError::Protected);
+    }
+    match wait::for_script(c, TIMELINE_RENDERED).await {
+        Ok(()) => {}
+        // Empty, or hidden from us
+        Err(e) if timeline != Timeline::Posts && e.is::<wait::Timeout>() => {
+            info!(
+                "Nothing to see on the {} tab of {user_id}",
+                timeline.as_str()
+            );
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e.into()),
+    }
     let username = {
         let doc = Html::parse_document(&c.source().await?);
         let div_selector = &Selector::parse("div").unwrap();
@@ -24,12 +170,8 @@ async fn get_recent_posts_from_user(c: &Client, user_id: &str, config: &Config)
             })
             .map(|e| e.text().collect::<Vec<_>>().join(" "))
             .next()
-            .ok_or(eyre!("Could not find username element"))?
+            .ok_or(FetchError::layout_changed("div[data-testid=UserName]"))?
             .split(" @") // <username> @<user_id>
-            .map(|s| {
-                println!("{s}");
-                s
-            })
             .next()
             .ok_or(eyre!("Username was not in '<username> @<user_id>'"))?
             .trim()
@@ -37,54 +179,705 @@ async fn get_recent_posts_from_user(c: &Client, user_id: &str, config: &Config)
     };
     debug!("Downloading data for {username}");
 
+    // Usernames aren't case sensitive, and links use the profile's spelling
+    let is_wanted = |link: &str| {
+        timeline.has_others_posts()
+            || split_status_link(link).is_some_and(|(user, _)| user.eq_ignore_ascii_case(user_id))
+    };
+    let links = collect_status_links(c, config, timeline == Timeline::Media, is_wanted).await?;
+    let links = links.into_iter().filter(|link| {
+        let wanted = is_wanted(link);
+        if !wanted {
+            debug!("Skipping {link}, it isn't by {user_id}");
+        }
+        wanted
+    });
+    get_posts(c, links, config, archived).await
+}
+
+/// Fetches the latest posts matching the search `query`, like
+/// [`get_recent_posts_from_user`] does.
+pub async fn get_posts_from_search(
+    c: &WrappedClient,
+    query: &str,
+    config: &Config,
+    archived: &Archived,
+) -> FetchResult<Vec<FetchedPost>> {
+    let query_component =
+        url::form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>();
+    let url = format!("https://twitter.com/search?q={query_component}&f=live");
+    get_posts_from_timeline(c, &url, config, archived).await
+}
+
+/// Fetches the latest posts in the timeline of the list with `list_id`.
+pub async fn get_posts_from_list(
+    c: &WrappedClient,
+    list_id: &str,
+    config: &Config,
+    archived: &Archived,
+) -> FetchResult<Vec<FetchedPost>> {
+    let url = format!("https://twitter.com/i/lists/{list_id}");
+    get_posts_from_timeline(c, &url, config, archived).await
+}
+
+/// Fetches the latest posts in the timeline at `url`, whoever they're by.
+async fn get_posts_from_timeline(
+    c: &WrappedClient,
+    url: &str,
+    config: &Config,
+    archived: &Archived,
+) -> FetchResult<Vec<FetchedPost>> {
+    c.goto(url).await?;
+    check_login_wall(c).await?;
+    match wait::for_script(c, wait::POSTS_RENDERED).await {
+        Ok(()) => {}
+        Err(e) if e.is::<wait::Timeout>() => {
+            info!("Nothing to see at {url}");
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e.into()),
+    }
+    let links = collect_status_links(c, config, false, |_| true).await?;
+    get_posts(c, links, config, archived).await
+}
+
+/// Scrolls through the timeline in the page, collecting links to its posts
+/// until there are enough and the last one is `wanted`, or it stops growing,
+/// as timelines and tabs can have fewer posts than we'd take. With
+/// `media_grid`, posts are also taken from the grid of the media tab.
+async fn collect_status_links(
+    c: &WrappedClient,
+    config: &Config,
+    media_grid: bool,
+    wanted: impl Fn(&str) -> bool,
+) -> FetchResult<indexmap::IndexSet<String>> {
     let re = Regex::new("^/\\w+/status/\\d+$").unwrap();
-    let anchor_selector = &Selector::parse("a").unwrap();
     let article_selector = &Selector::parse("article").unwrap();
-    let user_status_format = &format!("/{user_id}");
     let mut links = indexmap::IndexSet::new();
+    let mut unchanged = 0;
 
     while links.len() < config.fetch_config.max_links_per_fetch
-        || !links
-            .last()
-            .map(|l: &String| l.starts_with(user_status_format))
-            .unwrap_or(true)
+        || !links.last().map(|l: &String| wanted(l)).unwrap_or(true)
     {
-        c.execute("window.scrollBy(0,300);", vec![]).await?;
-        sleep_secs(1).await;
+        c.scroll_by(300).await?;
 
         let s = c.source().await?;
-        let doc = Html::parse_document(&s);
-        let link_iter = doc
-            .select(article_selector)
-            .flat_map(|article| article.select(anchor_selector))
-            .filter_map(|e| e.value().attr("href"))
-            .filter(|l| re.is_match(l))
-            .map(|l| l.to_owned());
-
-        links.extend(link_iter);
+        let before = links.len();
+        {
+            let doc = Html::parse_document(&s);
+            // Only each article's own link, as quoted posts in it link to
+            // themselves too
+            let link_iter = doc
+                .select(article_selector)
+                .filter_map(own_status_link)
+                .filter(|l| re.is_match(l))
+                .map(|l| l.to_owned());
+
+            links.extend(link_iter);
+            if media_grid {
+                links.extend(media_grid_links(&doc));
+            }
+        }
         debug!("Got {} posts so far", links.len());
+
+        if links.len() == before {
+            unchanged += 1;
+            if unchanged > config.fetch_config.max_retries {
+                debug!("Reached the end of the timeline");
+                break;
+            }
+            wait::for_network_idle(c).await?;
+        } else {
+            unchanged = 0;
+        }
     }
 
     info!("Ended searching with {} posts", links.len());
+    Ok(links)
+}
 
+/// Fetches the post at each of `links`, screenshotting them if enabled unless
+/// they're already `archived` with one. Posts that fail are left out, unless
+/// the failure would stop every other one too.
+async fn get_posts(
+    c: &WrappedClient,
+    links: impl IntoIterator<Item = String>,
+    config: &Config,
+    archived: &Archived,
+) -> FetchResult<Vec<FetchedPost>> {
     let mut posts = vec![];
-    for i in 0..links.len() {
-        let link = links.get_index(i).unwrap();
-        if link.starts_with(user_status_format) {
-            let post = get_post(c, link).await?;
-            posts.push(post);
+    for link in links {
+        let Some((_, id)) = split_status_link(&link) else {
             continue;
-        } else {
-            bail!("Getting retweets not supported!")
+        };
+        let screenshot = config.storage_config.screenshots && !archived.screenshotted.contains(id);
+        let revisions = archived.revisions.get(id).map_or(&[][..], |r| r);
+        match get_post(c, &link, screenshot, revisions).await {
+            Ok(post) => posts.push(post),
+            // The rest would fail the same way
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    FetchErrorKind::LoginRequired
+                        | FetchErrorKind::RateLimited
+                        | FetchErrorKind::Driver
+                ) =>
+            {
+                return Err(e)
+            }
+            Err(e) => warn!("Failed fetching {link}, skipping it: {e:#}"),
         }
     }
-
     Ok(posts)
 }
 
-async fn get_post(_c: &Client, link: &str) -> Result<()> {
-    let _full_link = get_post_full_link(link);
-    //c.goto(full_link).await?;
-    //sleep_secs(3).await;
-    bail!("TODO: Cannot download a post yet");
+/// Messages the site shows on the status page of a post that can't be seen.
+const UNAVAILABLE_STATES: &[&str] = &[
+    "This post is unavailable",
+    "This Tweet is unavailable",
+    "This post was deleted by the post author",
+    "This Tweet was deleted by the Tweet author",
+    "This post is from a suspended account",
+    "This Tweet is from a suspended account",
+    "Hmm...this page doesn’t exist",
+    "Hmm...this page doesn't exist",
+];
+
+/// Returns why a post in `src` can't be seen, if one can't. It may not be the
+/// post the page is for.
+fn unavailable_state(src: &str) -> Option<&'static str> {
+    UNAVAILABLE_STATES
+        .iter()
+        .find(|s| src.contains(*s))
+        .copied()
+}
+
+/// Splits a `/<user>/status/<id>` link into the user and the id.
+pub fn split_status_link(link: &str) -> Option<(&str, &str)> {
+    let path = link
+        .trim_start_matches("https://")
+        .trim_start_matches("twitter.com")
+        .trim_start_matches('/');
+    let mut parts = path.split('/');
+    let user = parts.next()?;
+    (parts.next()? == "status").then_some(())?;
+    let id = parts.next()?;
+    Some((user, id))
+}
+
+/// A post as shown in an `article`.
+struct ParsedArticle {
+    id: String,
+    username: String,
+    link: String,
+    entities: RichText,
+    posted_at: DateTime<Utc>,
+    /// Link to the post's edit history, if it was edited
+    history_link: Option<String>,
+    in_reply_to: Option<String>,
+    quote_link: Option<String>,
+    poll: Option<Poll>,
+    card: Option<LinkCard>,
+    community_note: Option<CommunityNote>,
+    media: Vec<Media>,
+}
+
+/// The timestamp of the post in `article`, and the link to it that wraps it.
+/// It comes before any quoted post's, which tells them apart.
+fn own_status_time(article: ElementRef) -> Option<(ElementRef, &str)> {
+    let time_selector = &Selector::parse("a > time").unwrap();
+    article.select(time_selector).find_map(|time| {
+        let link = time
+            .parent()
+            .and_then(ElementRef::wrap)
+            .and_then(|a| a.value().attr("href"))?;
+        split_status_link(link)?;
+        Some((time, link))
+    })
+}
+
+fn own_status_link(article: ElementRef) -> Option<&str> {
+    own_status_time(article).map(|(_, link)| link)
+}
+
+/// Link to the post quoted in `article`, from the links in its container.
+/// The post itself can link to other posts too, like its latest version.
+fn quoted_status_link(article: ElementRef, own_id: &str) -> Option<String> {
+    let container_selector = &Selector::parse("[role=link]").unwrap();
+    let anchor_selector = &Selector::parse("a").unwrap();
+    let quote = article.select(container_selector).find(|e| is_quote(*e))?;
+    quote
+        .select(anchor_selector)
+        .filter_map(|a| a.value().attr("href"))
+        .find_map(|href| {
+            let (user, id) = split_status_link(href)?;
+            (id != own_id).then(|| get_post_full_link(&format!("/{user}/status/{id}")))
+        })
+}
+
+/// Parses the post in `article`.
+fn parse_article(article: ElementRef) -> FetchResult<Option<ParsedArticle>> {
+    let text_selector = &Selector::parse("div[data-testid=tweetText]").unwrap();
+    let anchor_selector = &Selector::parse("a").unwrap();
+
+    let Some((time, link)) = own_status_time(article) else {
+        return Ok(None);
+    };
+    let (username, id) = split_status_link(link).unwrap();
+
+    let posted_at = time
+        .value()
+        .attr("datetime")
+        .ok_or(FetchError::layout_changed("time[datetime]"))?;
+    let posted_at = DateTime::parse_from_rfc3339(posted_at)
+        .wrap_err_with(|| format!("Failed parsing post date `{posted_at}`"))?
+        .with_timezone(&Utc);
+    // Posts with only media have no text
+    let mut entities = article
+        .select(text_selector)
+        .next()
+        .map(RichText::parse)
+        .unwrap_or_default();
+    // Link cards point to the same shortened link as the text, and sometimes
+    // say where it goes when the text doesn't
+    let short_links = article
+        .select(anchor_selector)
+        .filter_map(|a| {
+            let href = a.value().attr("href").filter(|href| is_short_url(href))?;
+            let expanded = expanded_url(a, href);
+            (expanded != href).then(|| (href.to_owned(), expanded))
+        })
+        .collect::<HashMap<_, _>>();
+    entities.resolve(&short_links);
+    // Links to the history of the latest version, whichever one this is
+    let history_link = article
+        .select(anchor_selector)
+        .filter(|a| !in_quote(*a, article))
+        .filter_map(|a| a.value().attr("href"))
+        .find(|href| {
+            href.ends_with("/history")
+                && split_status_link(href).is_some_and(|(user, _)| user == username)
+        })
+        .map(get_post_full_link);
+
+    Ok(Some(ParsedArticle {
+        id: id.to_owned(),
+        username: username.to_owned(),
+        link: get_post_full_link(link),
+        entities,
+        posted_at,
+        history_link,
+        in_reply_to: None,
+        quote_link: quoted_status_link(article, id),
+        poll: parse_poll(article),
+        card: parse_link_card(article, &short_links),
+        community_note: parse_community_note(article),
+        media: parse_media(article),
+    }))
+}
+
+/// Whether `article` has a "Replying to" note. The status page only shows one
+/// when the post it replies to isn't right above it, because it's unavailable
+/// or hidden.
+fn says_replying_to(article: ElementRef) -> bool {
+    article.descendants().any(|n| {
+        n.value()
+            .as_text()
+            .is_some_and(|t| t.trim_start().starts_with("Replying to"))
+            && n.parent()
+                .and_then(ElementRef::wrap)
+                .is_some_and(|e| !in_quote(e, article))
+    })
+}
+
+/// Finds the post with status `link` in the page, and parses it.
+fn parse_post(link: &str, src: &str) -> FetchResult<Option<ParsedArticle>> {
+    let (_, id) = split_status_link(link).ok_or(eyre!("`{link}` is not a link to a post"))?;
+    let doc = Html::parse_document(src);
+    let article_selector = &Selector::parse("article").unwrap();
+    // The status page shows the conversation the post is in above it, so the
+    // post right before it is the one it replies to, unless the post says
+    // who it replies to instead
+    let mut previous = None;
+    for article in doc.select(article_selector) {
+        if let Some(mut post) = parse_article(article)? {
+            if post.id == id {
+                post.in_reply_to = previous.filter(|_| !says_replying_to(article));
+                return Ok(Some(post));
+            }
+            previous = Some(post.id);
+        }
+    }
+    Ok(None)
+}
+
+/// Parses every version of a post in its edit history page, oldest first.
+fn parse_history(src: &str) -> FetchResult<Vec<PostRevision>> {
+    let doc = Html::parse_document(src);
+    let article_selector = &Selector::parse("article").unwrap();
+    let mut revisions = vec![];
+    for article in doc.select(article_selector) {
+        if let Some(post) = parse_article(article)? {
+            revisions.push(PostRevision {
+                id: post.id,
+                link: post.link,
+                text: post.entities.plain(),
+                entities: post.entities,
+                posted_at: post.posted_at,
+            });
+        }
+    }
+    revisions.sort_by_key(|r| r.posted_at);
+    revisions.dedup_by(|a, b| a.id == b.id);
+    Ok(revisions)
+}
+
+async fn get_revisions(c: &WrappedClient, history_link: &str) -> FetchResult<Vec<PostRevision>> {
+    c.goto(history_link).await?;
+    check_login_wall(c).await?;
+    wait::for_script(c, wait::POSTS_RENDERED).await?;
+    wait::for_network_idle(c).await?;
+    let revisions = parse_history(&c.source().await?)?;
+    if revisions.is_empty() {
+        return Err(FetchError::layout_changed("article a > time"));
+    }
+    Ok(revisions)
+}
+
+/// Sets the size of each of `media` as loaded in the page. Media that hasn't
+/// loaded yet is left without one.
+async fn fill_media_dimensions(c: &WrappedClient, media: &mut [Media]) -> FetchResult<()> {
+    let script = "return Array.from(document.querySelectorAll(
+        '[data-testid=tweetPhoto] img, [data-testid=videoPlayer] video'
+    )).map(e => e.tagName === 'IMG'
+        ? [e.src, e.naturalWidth, e.naturalHeight]
+        : [e.poster, e.videoWidth, e.videoHeight]);";
+    let res = c.execute(script, vec![]).await?;
+    let sizes = res
+        .as_array()
+        .ok_or(eyre!("Media dimensions were not an array"))?
+        .iter()
+        .filter_map(|v| {
+            let src = v.get(0)?.as_str()?;
+            let width = v.get(1)?.as_u64()? as u32;
+            let height = v.get(2)?.as_u64()? as u32;
+            (width != 0 && height != 0).then(|| (src.to_owned(), (width, height)))
+        })
+        .collect::<HashMap<_, _>>();
+    for m in media.iter_mut() {
+        if let Some(&(width, height)) = m.page_url().and_then(|url| sizes.get(url)) {
+            m.width = Some(width);
+            m.height = Some(height);
+        }
+    }
+    Ok(())
+}
+
+/// The id of the video a poster or playlist URL is for, i.e. `<id>` in
+/// `https://video.twimg.com/ext_tw_video/<id>/pu/pl/<name>.m3u8`.
+fn video_id(url: &str) -> Option<&str> {
+    let re = Regex::new(
+        r"^https://(?:pbs|video)\.twimg\.com/(?:ext_tw|amplify)_video(?:_thumb)?/(\d+)/",
+    )
+    .unwrap();
+    re.captures(url)?.get(1).map(|id| id.as_str())
+}
+
+/// Sets the HLS playlist of each video in `media` that's streamed, from the
+/// `playlists` with the same video id as its poster. The page also plays
+/// videos of other posts, so videos without a matching one are left unset.
+fn match_video_playlists<'a>(media: &mut [Media], playlists: impl IntoIterator<Item = &'a str>) {
+    // Each video has a master playlist, which links to one for each quality
+    let master_re = Regex::new(r"^https://video\.twimg\.com/.*/pl/[^/]+\.m3u8").unwrap();
+    let mut by_id = HashMap::new();
+    for playlist in playlists.into_iter().filter(|p| master_re.is_match(p)) {
+        if let Some(id) = video_id(playlist) {
+            by_id.entry(id).or_insert(playlist);
+        }
+    }
+    let streamed = media
+        .iter_mut()
+        .filter(|m| m.kind == MediaKind::Video && m.url.is_none());
+    for m in streamed {
+        let id = m.preview_url.as_deref().and_then(video_id);
+        m.playlist_url = id.and_then(|id| by_id.get(id)).map(|p| p.to_string());
+    }
+}
+
+/// Sets the HLS playlist of each video in `media` that's streamed, from the
+/// ones the page loaded.
+async fn fill_video_playlists(c: &WrappedClient, media: &mut [Media]) -> FetchResult<()> {
+    let script = "return performance.getEntriesByType('resource').map(e => e.name);";
+    let res = c.execute(script, vec![]).await?;
+    let loaded = res
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str());
+    match_video_playlists(media, loaded);
+    Ok(())
+}
+
+/// Takes a PNG screenshot of the post with `id`, as shown on its status page.
+async fn screenshot_post(c: &WrappedClient, id: &str) -> FetchResult<Vec<u8>> {
+    // The article whose own timestamp links to the post
+    let suffix = format!("/status/{id}");
+    let xpath = format!(
+        "//article[.//a[time][substring(@href, string-length(@href) - {len}) = '{suffix}']]",
+        len = suffix.len() - 1
+    );
+    let article = wait::for_element(c, Locator::XPath(&xpath)).await?;
+    Ok(article.screenshot().await?)
+}
+
+/// Fetches the post at status `link`, and every earlier version of it if it
+/// was edited, unless its latest version is already in `known_revisions`.
+/// Errors with [`FetchError::PostUnavailable`] if it was deleted, or can't be
+/// seen.
+pub async fn get_post(
+    c: &WrappedClient,
+    link: &str,
+    screenshot: bool,
+    known_revisions: &[PostRevision],
+) -> FetchResult<FetchedPost> {
+    let full_link = get_post_full_link(link);
+    c.goto(&full_link).await?;
+    check_login_wall(c).await?;
+    let post = wait::until("the post to load", wait::TIMEOUT, || async {
+        let src = c.source().await?;
+        if let Some(post) = parse_post(link, &src).map_err(Report::from)? {
+            return Ok(Some(Ok(post)));
+        }
+        if unavailable_state(&src).is_none() {
+            return Ok(None);
+        }
+        // A deleted post it replies to or quotes shows up before the post
+        // itself, so the post is only gone if it's still missing once the page
+        // has loaded
+        wait::for_network_idle(c).await?;
+        let src = c.source().await?;
+        if let Some(post) = parse_post(link, &src).map_err(Report::from)? {
+            return Ok(Some(Ok(post)));
+        }
+        Ok(unavailable_state(&src).map(|reason| {
+            Err(FetchError::PostUnavailable {
+                reason: reason.to_owned(),
+            })
+        }))
+    })
+    .await??;
+    let mut media = post.media;
+    if !media.is_empty() {
+        fill_media_dimensions(c, &mut media)
+            .await
+            .wrap_err("Failed getting media dimensions")?;
+        fill_video_playlists(c, &mut media)
+            .await
+            .wrap_err("Failed getting video playlists")?;
+    }
+    // The post is still worth archiving without one
+    let screenshot = if screenshot {
+        match screenshot_post(c, &post.id).await {
+            Ok(png) => Some(png),
+            Err(e) => {
+                warn!("Failed taking screenshot of {full_link}: {e:#}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut entities = post.entities;
+    let latest_id = post
+        .history_link
+        .as_deref()
+        .and_then(split_status_link)
+        .map(|(_, id)| id);
+    let revisions = match &post.history_link {
+        // Edits make a new version, so the history didn't change since
+        Some(_)
+            if known_revisions
+                .last()
+                .zip(latest_id)
+                .is_some_and(|(r, id)| r.id == id) =>
+        {
+            debug!("Post {} has no new versions", post.id);
+            entities = known_revisions.last().unwrap().entities.clone();
+            known_revisions.to_vec()
+        }
+        Some(history_link) => {
+            let revisions = get_revisions(c, history_link)
+                .await
+                .wrap_err_with(|| format!("Failed getting edits of {full_link}"))?;
+            debug!("Post {} has {} versions", post.id, revisions.len());
+            // The status page of an earlier version only notes there's a
+            // newer one
+            entities = revisions.last().unwrap().entities.clone();
+            revisions
+        }
+        None => vec![],
+    };
+    debug!("Got post {}", post.id);
+    Ok(FetchedPost {
+        id: post.id,
+        username: post.username,
+        link: post.link,
+        text: entities.plain(),
+        entities,
+        posted_at: post.posted_at,
+        in_reply_to: post.in_reply_to,
+        quote_link: post.quote_link,
+        poll: post.poll,
+        card: post.card,
+        community_note: post.community_note,
+        media,
+        revisions,
+        screenshot,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn article(user: &str, id: &str, inner: &str) -> String {
+        format!(
+            r#"<article><a href="/{user}/status/{id}"><time datetime="2023-10-01T12:00:00.000Z"></time></a><div data-testid="tweetText"><span>Post {id}</span></div>{inner}</article>"#
+        )
+    }
+
+    #[test]
+    fn replies_to_deleted_posts_are_found() {
+        let src = format!(
+            r#"<html><body><div>This post was deleted by the post author. Learn more</div>{}</body></html>"#,
+            article("bob", "2", "")
+        );
+        assert!(unavailable_state(&src).is_some());
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(post.id, "2");
+    }
+
+    #[test]
+    fn quotes_of_unavailable_posts_are_found() {
+        let src = format!(
+            r#"<html><body>{}</body></html>"#,
+            article(
+                "bob",
+                "2",
+                "<div><span>This post is unavailable.</span></div>"
+            )
+        );
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(post.id, "2");
+    }
+
+    #[test]
+    fn history_links_are_the_posts_own() {
+        let quote = format!(
+            r#"<div role="link">{}</div>"#,
+            r#"<a href="/bob/status/1/history">Last edited</a>"#
+        );
+        let src = article("bob", "2", &quote);
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(post.history_link, None);
+
+        let src = article(
+            "bob",
+            "2",
+            r#"<a href="/bob/status/3/history">Last edited</a>"#,
+        );
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(
+            post.history_link.as_deref(),
+            Some("https://twitter.com/bob/status/3/history")
+        );
+    }
+
+    #[test]
+    fn replies_are_to_the_post_above_them() {
+        let src = format!("{}{}", article("alice", "1", ""), article("bob", "2", ""));
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(post.in_reply_to.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn replies_to_posts_that_arent_shown_have_no_parent() {
+        let note = r#"<div>Replying to <a href="/carol">@carol</a></div>"#;
+        let src = format!(
+            "{}<div>This post is unavailable.</div>{}",
+            article("alice", "1", ""),
+            article("bob", "3", note)
+        );
+        let post = parse_post("/bob/status/3", &src).unwrap().unwrap();
+        assert_eq!(post.in_reply_to, None);
+    }
+
+    fn video(poster: &str) -> Media {
+        Media {
+            kind: MediaKind::Video,
+            url: None,
+            preview_url: Some(poster.to_owned()),
+            playlist_url: None,
+            path: None,
+            alt: None,
+            width: None,
+            height: None,
+        }
+    }
+
+    #[test]
+    fn playlists_go_with_their_videos() {
+        let mut media = [
+            video("https://pbs.twimg.com/ext_tw_video_thumb/222/pu/img/b.jpg"),
+            video("https://pbs.twimg.com/amplify_video_thumb/333/img/c.jpg"),
+            video("https://pbs.twimg.com/ext_tw_video_thumb/444/pu/img/d.jpg"),
+        ];
+        // The post it replies to plays first
+        let loaded = [
+            "https://video.twimg.com/ext_tw_video/111/pu/pl/a.m3u8?tag=12",
+            "https://video.twimg.com/amplify_video/333/pl/c.m3u8",
+            "https://video.twimg.com/ext_tw_video/222/pu/pl/b.m3u8",
+            "https://video.twimg.com/ext_tw_video/222/pu/pl/avc1/720x1280/b.m3u8",
+        ];
+        match_video_playlists(&mut media, loaded);
+        let playlists = media.map(|m| m.playlist_url);
+        assert_eq!(
+            playlists,
+            [
+                Some("https://video.twimg.com/ext_tw_video/222/pu/pl/b.m3u8".to_owned()),
+                Some("https://video.twimg.com/amplify_video/333/pl/c.m3u8".to_owned()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn status_links_are_split() {
+        assert_eq!(split_status_link("/Foo/status/1"), Some(("Foo", "1")));
+        assert_eq!(
+            split_status_link("https://twitter.com/foo/status/2/history"),
+            Some(("foo", "2"))
+        );
+        assert_eq!(split_status_link("/foo/likes"), None);
+    }
+
+    #[test]
+    fn quotes_are_only_in_their_container() {
+        let src = article(
+            "bob",
+            "2",
+            r#"<a href="/bob/status/3">See the latest version</a>"#,
+        );
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(post.quote_link, None);
+
+        let quote = r#"<div role="link"><a href="/carol/status/1"><time datetime="2023-09-01T12:00:00.000Z"></time></a></div>"#;
+        let src = article("bob", "2", quote);
+        let post = parse_post("/bob/status/2", &src).unwrap().unwrap();
+        assert_eq!(
+            post.quote_link.as_deref(),
+            Some("https://twitter.com/carol/status/1")
+        );
+    }
 }
@@ -0,0 +1,378 @@
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use scraper::{ElementRef, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::fetch::text::expanded_url;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Poll {
+    pub options: Vec<PollOption>,
+    /// Total votes, if shown
+    pub votes: Option<u64>,
+    /// When voting ends or ended, as close as the page says
+    pub ends_at: Option<DateTime<Utc>>,
+    pub ended: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PollOption {
+    pub label: String,
+    /// Only shown once voted, or once the poll ended
+    pub percent: Option<f32>,
+}
+
+impl PollOption {
+    /// Votes for the option, worked out from its share of the total.
+    pub fn votes(&self, total: u64) -> Option<u64> {
+        Some((self.percent? as f64 / 100.0 * total as f64).round() as u64)
+    }
+}
+
+/// The preview of a linked page.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LinkCard {
+    pub url: String,
+    pub domain: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub thumbnail_url: Option<String>,
+}
+
+/// Context added by readers to a post.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommunityNote {
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Photo,
+    Video,
+    Gif,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Media {
+    pub kind: MediaKind,
+    /// The media itself. Unknown for videos streamed in pieces
+    pub url: Option<String>,
+    /// What videos and GIFs show before playing
+    pub preview_url: Option<String>,
+    /// HLS playlist videos are streamed from
+    #[serde(default)]
+    pub playlist_url: Option<String>,
+    /// Where the media was downloaded to, in the media store
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Description written by the author
+    pub alt: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl Media {
+    /// The URL the media is shown from in the page, to match it with its
+    /// dimensions.
+    pub fn page_url(&self) -> Option<&str> {
+        match self.kind {
+            MediaKind::Photo => self.url.as_deref(),
+            MediaKind::Video | MediaKind::Gif => self.preview_url.as_deref(),
+        }
+    }
+}
+
+fn find_by_testid<'a>(e: ElementRef<'a>, testid: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(&format!("[data-testid=\"{testid}\"]")).unwrap();
+    let found = e.select(&selector).next();
+    found
+}
+
+/// Like [`find_by_testid`], but skips what's in a post quoted in `article`.
+fn find_own_by_testid<'a>(article: ElementRef<'a>, testid: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(&format!("[data-testid=\"{testid}\"]")).unwrap();
+    let found = article.select(&selector).find(|e| !in_quote(*e, article));
+    found
+}
+
+/// Leaf texts in `e`, in order, skipping empty ones.
+fn texts(e: ElementRef) -> Vec<String> {
+    e.text()
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_owned())
+        .collect()
+}
+
+/// Parses counts shown as `1,234`, `12.3K` or `1.2M`.
+fn parse_count(s: &str) -> Option<u64> {
+    let s = s.replace(',', "");
+    let (n, multiplier) = match s.chars().last()? {
+        'K' => (&s[..s.len() - 1], 1_000.0),
+        'M' => (&s[..s.len() - 1], 1_000_000.0),
+        _ => (s.as_str(), 1.0),
+    };
+    Some((n.parse::<f64>().ok()? * multiplier).round() as u64)
+}
+
+pub fn parse_poll(article: ElementRef) -> Option<Poll> {
+    let poll = find_own_by_testid(article, "cardPoll")?;
+    let percent_re = Regex::new(r"^(\d+(?:\.\d+)?)%$").unwrap();
+    let votes_re = Regex::new(r"^([\d,.]+[KM]?) votes?").unwrap();
+    let left_re = Regex::new(r"(\d+) (minute|hour|day)s? left").unwrap();
+
+    let mut options: Vec<PollOption> = vec![];
+    let mut votes = None;
+    let mut ended = false;
+    let mut ends_at = None;
+    // The footer is split in pieces, i.e. `1,234 votes`, `·`, `Final results`
+    for line in texts(poll) {
+        let mut footer = false;
+        if let Some(c) = votes_re.captures(&line) {
+            votes = parse_count(&c[1]);
+            footer = true;
+        }
+        if line.contains("Final results") {
+            ended = true;
+            footer = true;
+        }
+        if let Some(c) = left_re.captures(&line) {
+            let n = c[1].parse().unwrap_or(0);
+            let left = match &c[2] {
+                "minute" => Duration::minutes(n),
+                "hour" => Duration::hours(n),
+                _ => Duration::days(n),
+            };
+            ends_at = Some(Utc::now() + left);
+            footer = true;
+        }
+        if footer || line == "·" {
+            continue;
+        }
+        match (percent_re.captures(&line), options.last_mut()) {
+            // Percentages follow the option they're for
+            (Some(c), Some(option)) => option.percent = c[1].parse().ok(),
+            _ => options.push(PollOption {
+                label: line,
+                percent: None,
+            }),
+        }
+    }
+    if options.is_empty() {
+        return None;
+    }
+    Some(Poll {
+        options,
+        votes,
+        ends_at,
+        ended,
+    })
+}
+
+/// Parses the link card in `article`. `short_links` says where shortened
+/// links in the post go.
+pub fn parse_link_card(
+    article: ElementRef,
+    short_links: &HashMap<String, String>,
+) -> Option<LinkCard> {
+    let card = find_own_by_testid(article, "card.wrapper")?;
+    // Polls are cards too
+    if find_by_testid(card, "cardPoll").is_some() {
+        return None;
+    }
+    let anchor_selector = &Selector::parse("a[href]").unwrap();
+    let img_selector = &Selector::parse("img[src]").unwrap();
+
+    let a = card.select(anchor_selector).next()?;
+    let href = a.value().attr("href").unwrap();
+    let url = short_links
+        .get(href)
+        .cloned()
+        .unwrap_or_else(|| expanded_url(a, href));
+
+    // Large cards show the domain over the image, and small ones show it
+    // with the title and description
+    let detail = find_by_testid(card, "card.layoutLarge.detail")
+        .or_else(|| find_by_testid(card, "card.layoutSmall.detail"));
+    let mut lines = detail.map(texts).unwrap_or_default().into_iter();
+    let (domain, title, description) = if detail.is_some() {
+        (lines.next(), lines.next(), lines.next())
+    } else {
+        // Cards with only an image say what they are in its alt text
+        let title = card
+            .select(img_selector)
+            .next()
+            .and_then(|img| img.value().attr("alt"))
+            .map(|alt| alt.to_owned());
+        (None, title, None)
+    };
+    let thumbnail_url = card
+        .select(img_selector)
+        .next()
+        .and_then(|img| img.value().attr("src"))
+        .map(|src| src.to_owned());
+
+    Some(LinkCard {
+        url,
+        domain,
+        title,
+        description,
+        thumbnail_url,
+    })
+}
+
+pub fn parse_community_note(article: ElementRef) -> Option<CommunityNote> {
+    let note = find_own_by_testid(article, "birdwatch-pivot")?;
+    const HEADERS: &[&str] = &[
+        "Readers added context they thought people might want to know",
+        "Readers added context",
+        "Context is written by people who use X, and appears when rated helpful by others.",
+        "Find out more",
+        "Do you find this helpful?",
+        "Rate it",
+    ];
+    let text = texts(note)
+        .into_iter()
+        .filter(|t| !HEADERS.contains(&t.as_str()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    Some(CommunityNote { text })
+}
+
+/// Whether `e` is the container of a quoted post. Those act as a link, but
+/// aren't anchors like the links around a post's own photos.
+pub fn is_quote(e: ElementRef) -> bool {
+    e.value().name() != "a" && e.value().attr("role") == Some("link")
+}
+
+/// Whether `e` is inside a post quoted in `article`, rather than in the post
+/// itself.
+pub fn in_quote(e: ElementRef, article: ElementRef) -> bool {
+    e.ancestors()
+        .take_while(|n| n.id() != article.id())
+        .filter_map(ElementRef::wrap)
+        .any(is_quote)
+}
+
+/// Parses the photos, videos and GIFs attached to the post in `article`.
+/// Their dimensions aren't in the page source, and are filled in later.
+pub fn parse_media(article: ElementRef) -> Vec<Media> {
+    let media_selector =
+        &Selector::parse("[data-testid=tweetPhoto] img, [data-testid=videoPlayer] video").unwrap();
+    let mut media = vec![];
+    for e in article.select(media_selector) {
+        if in_quote(e, article) {
+            continue;
+        }
+        let value = e.value();
+        let src = value
+            .attr("src")
+            .filter(|src| src.starts_with("https://"))
+            .map(|src| src.to_owned());
+        if value.name() == "img" {
+            // Photos without a description say they're an image
+            let alt = value
+                .attr("alt")
+                .filter(|alt| !alt.is_empty() && *alt != "Image")
+                .map(|alt| alt.to_owned());
+            media.push(Media {
+                kind: MediaKind::Photo,
+                url: src,
+                preview_url: None,
+                playlist_url: None,
+                path: None,
+                alt,
+                width: None,
+                height: None,
+            });
+        } else {
+            // GIFs are looping videos served as a single file, and real
+            // videos are streamed from `blob:` URLs
+            let is_gif = src
+                .as_deref()
+                .map(|src| src.contains("/tweet_video/"))
+                .unwrap_or(false);
+            media.push(Media {
+                kind: if is_gif {
+                    MediaKind::Gif
+                } else {
+                    MediaKind::Video
+                },
+                url: src,
+                preview_url: value.attr("poster").map(|p| p.to_owned()),
+                playlist_url: None,
+                path: None,
+                alt: value
+                    .attr("aria-label")
+                    .filter(|l| *l != "Embedded video")
+                    .map(|l| l.to_owned()),
+                width: None,
+                height: None,
+            });
+        }
+    }
+    media
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    #[test]
+    fn quoted_attachments_are_the_quotes() {
+        let quote = r#"<div role="link">
+            <div data-testid="cardPoll"><span>Yes</span><span>No</span></div>
+            <div data-testid="card.wrapper"><a href="https://t.co/abc">Site</a></div>
+            <div data-testid="birdwatch-pivot"><span>Not true</span></div>
+        </div>"#;
+        let doc = Html::parse_fragment(&format!("<article>{quote}</article>"));
+        let article = doc
+            .select(&Selector::parse("article").unwrap())
+            .next()
+            .unwrap();
+        assert!(parse_poll(article).is_none());
+        assert!(parse_link_card(article, &HashMap::new()).is_none());
+        assert!(parse_community_note(article).is_none());
+
+        let doc = Html::parse_fragment(&format!(
+            r#"<article><div data-testid="cardPoll"><span>Maybe</span></div>{quote}</article>"#
+        ));
+        let article = doc
+            .select(&Selector::parse("article").unwrap())
+            .next()
+            .unwrap();
+        let poll = parse_poll(article).unwrap();
+        assert_eq!(poll.options.len(), 1);
+        assert_eq!(poll.options[0].label, "Maybe");
+    }
+
+    #[test]
+    fn own_photos_in_links_are_not_quoted() {
+        let doc = Html::parse_fragment(
+            r#"<article>
+            <a href="/bob/status/2/photo/1" role="link"><div data-testid="tweetPhoto">
+                <img src="https://pbs.twimg.com/media/own.jpg" alt="Own">
+            </div></a>
+            <div role="link"><a href="/carol/status/1/photo/1" role="link"><div data-testid="tweetPhoto">
+                <img src="https://pbs.twimg.com/media/quoted.jpg" alt="Quoted">
+            </div></a></div>
+        </article>"#,
+        );
+        let article = doc
+            .select(&Selector::parse("article").unwrap())
+            .next()
+            .unwrap();
+        let media = parse_media(article);
+        assert_eq!(media.len(), 1);
+        assert_eq!(
+            media[0].url.as_deref(),
+            Some("https://pbs.twimg.com/media/own.jpg")
+        );
+    }
+}
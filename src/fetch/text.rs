@@ -0,0 +1,298 @@
+use scraper::{ElementRef, Node};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Write};
+use url::Url;
+
+use crate::utils::escape;
+
+/// A piece of a post's text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Entity {
+    Text {
+        text: String,
+    },
+    Mention {
+        username: String,
+    },
+    Hashtag {
+        tag: String,
+    },
+    Cashtag {
+        symbol: String,
+    },
+    Url {
+        /// Where the link in the page points, usually a `t.co` redirect
+        url: String,
+        /// The full URL the post linked
+        expanded: String,
+        /// The shortened URL shown in the post
+        display: String,
+    },
+    /// Emoji are shown as images, with the emoji itself as alt text
+    Emoji {
+        alt: String,
+    },
+}
+
+/// The text of a post, split into entities.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RichText(pub Vec<Entity>);
+
+impl RichText {
+    /// Parses the contents of a `div[data-testid=tweetText]`.
+    pub fn parse(div: ElementRef) -> Self {
+        let mut text = RichText::default();
+        text.parse_children(div);
+        text
+    }
+
+    fn push_text(&mut self, s: &str) {
+        if let Some(Entity::Text { text }) = self.0.last_mut() {
+            text.push_str(s);
+        } else {
+            self.0.push(Entity::Text { text: s.to_owned() });
+        }
+    }
+
+    fn parse_children(&mut self, e: ElementRef) {
+        for child in e.children() {
+            match child.value() {
+                Node::Text(t) => self.push_text(t),
+                Node::Element(e) if e.name() == "img" => {
+                    if let Some(alt) = e.attr("alt") {
+                        self.0.push(Entity::Emoji {
+                            alt: alt.to_owned(),
+                        });
+                    }
+                }
+                Node::Element(e) if e.name() == "a" => {
+                    let a = ElementRef::wrap(child).unwrap();
+                    match e.attr("href").and_then(|href| link_entity(a, href)) {
+                        Some(entity) => self.0.push(entity),
+                        None => self.parse_children(a),
+                    }
+                }
+                Node::Element(_) => self.parse_children(ElementRef::wrap(child).unwrap()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Every shortened link in the text, and where it goes.
+    pub fn short_links(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().filter_map(|e| match e {
+            Entity::Url { url, expanded, .. } if is_short_url(url) && url != expanded => {
+                Some((url.as_str(), expanded.as_str()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Shortened links that weren't expanded.
+    pub fn unresolved_short_links(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter_map(|e| match e {
+                Entity::Url { url, expanded, .. } if is_short_url(url) && url == expanded => {
+                    Some(url.as_str())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Expands links whose own anchor didn't say where they go, with
+    /// `short_links` found elsewhere.
+    pub fn resolve(&mut self, short_links: &HashMap<String, String>) {
+        for e in &mut self.0 {
+            if let Entity::Url { url, expanded, .. } = e {
+                if url == expanded {
+                    if let Some(target) = short_links.get(url) {
+                        *expanded = target.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn plain(&self) -> String {
+        let mut s = String::new();
+        for entity in &self.0 {
+            match entity {
+                Entity::Text { text } => s.push_str(text),
+                Entity::Mention { username } => {
+                    let _ = write!(s, "@{username}");
+                }
+                Entity::Hashtag { tag } => {
+                    let _ = write!(s, "#{tag}");
+                }
+                Entity::Cashtag { symbol } => {
+                    let _ = write!(s, "${symbol}");
+                }
+                Entity::Url { expanded, .. } => s.push_str(expanded),
+                Entity::Emoji { alt } => s.push_str(alt),
+            }
+        }
+        s
+    }
+
+    /// Renders the text as HTML. Everything from the post is escaped, and
+    /// only `http(s)` links are kept, so it is safe to embed in a feed.
+    pub fn html(&self) -> String {
+        let mut html = String::new();
+        for entity in &self.0 {
+            match entity {
+                Entity::Text { text } => html.push_str(&escape(text).replace('\n', "<br>")),
+                Entity::Mention { username } => link(
+                    &mut html,
+                    &format!("https://twitter.com/{username}"),
+                    &format!("@{username}"),
+                ),
+                Entity::Hashtag { tag } => link(
+                    &mut html,
+                    &format!("https://twitter.com/hashtag/{}", url_component(tag)),
+                    &format!("#{tag}"),
+                ),
+                Entity::Cashtag { symbol } => link(
+                    &mut html,
+                    &format!(
+                        "https://twitter.com/search?q={}",
+                        url_component(&format!("${symbol}"))
+                    ),
+                    &format!("${symbol}"),
+                ),
+                Entity::Url {
+                    expanded, display, ..
+                } => link(&mut html, expanded, display),
+                Entity::Emoji { alt } => html.push_str(&escape(alt)),
+            }
+        }
+        html
+    }
+}
+
+fn link(html: &mut String, href: &str, text: &str) {
+    if href.starts_with("https://") || href.starts_with("http://") {
+        let _ = write!(html, r#"<a href="{}">{}</a>"#, escape(href), escape(text));
+    } else {
+        html.push_str(&escape(text));
+    }
+}
+
+fn url_component(s: &str) -> String {
+    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+}
+
+/// Text of `e`, without what's only there for screen readers or copying.
+fn visible_text(e: ElementRef) -> String {
+    let mut s = String::new();
+    for child in e.children() {
+        match child.value() {
+            Node::Text(t) => s.push_str(t),
+            Node::Element(el) if el.attr("aria-hidden") == Some("true") => {}
+            Node::Element(_) => s.push_str(&visible_text(ElementRef::wrap(child).unwrap())),
+            _ => {}
+        }
+    }
+    s
+}
+
+fn is_web_url(s: &str) -> bool {
+    Url::parse(s)
+        .map(|u| matches!(u.scheme(), "http" | "https") && u.host_str().is_some())
+        .unwrap_or(false)
+}
+
+/// Whether `url` goes through the site's link shortener.
+pub fn is_short_url(url: &str) -> bool {
+    Url::parse(url)
+        .map(|u| u.host_str() == Some("t.co"))
+        .unwrap_or(false)
+}
+
+/// Where the link `a` to `href` really goes. Shortened links say where they
+/// go in their title, or in their text, where long URLs are shortened by
+/// hiding the rest of them.
+pub fn expanded_url(a: ElementRef, href: &str) -> String {
+    if let Some(title) = a.value().attr("title").filter(|t| is_web_url(t)) {
+        return title.to_owned();
+    }
+    let text = a.text().collect::<String>();
+    let text = text.trim().trim_end_matches('…');
+    if is_short_url(href) && is_web_url(text) {
+        text.to_owned()
+    } else {
+        href.to_owned()
+    }
+}
+
+fn link_entity(a: ElementRef, href: &str) -> Option<Entity> {
+    let text = a.text().collect::<String>();
+    if href.starts_with("/hashtag/") {
+        Some(Entity::Hashtag {
+            tag: text.trim_start_matches(['#', '＃']).to_owned(),
+        })
+    } else if href.contains("cashtag_click") {
+        Some(Entity::Cashtag {
+            symbol: text.trim_start_matches('$').to_owned(),
+        })
+    } else if href.starts_with('/') && text.starts_with('@') {
+        Some(Entity::Mention {
+            username: text.trim_start_matches('@').to_owned(),
+        })
+    } else if href.starts_with("http") {
+        Some(Entity::Url {
+            url: href.to_owned(),
+            expanded: expanded_url(a, href),
+            display: visible_text(a),
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(expanded: &str, display: &str) -> Entity {
+        Entity::Url {
+            url: "https://t.co/abc".to_owned(),
+            expanded: expanded.to_owned(),
+            display: display.to_owned(),
+        }
+    }
+
+    #[test]
+    fn html_escapes_text_and_links() {
+        let text = RichText(vec![
+            Entity::Text {
+                text: "<b>\"a\" & 'b'</b>\n".to_owned(),
+            },
+            url("https://example.com/?a=1&b=\"><script>", "<example>"),
+        ]);
+        assert_eq!(
+            text.html(),
+            "&lt;b&gt;&quot;a&quot; &amp; &apos;b&apos;&lt;/b&gt;<br>\
+             <a href=\"https://example.com/?a=1&amp;b=&quot;&gt;&lt;script&gt;\">&lt;example&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn html_drops_links_that_are_not_http() {
+        for href in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            "data:text/html,<script>",
+            "vbscript:msgbox",
+            "//example.com",
+        ] {
+            let text = RichText(vec![url(href, "click")]);
+            assert_eq!(text.html(), "click", "{href}");
+        }
+        let text = RichText(vec![url("http://example.com", "ok")]);
+        assert_eq!(text.html(), r#"<a href="http://example.com">ok</a>"#);
+    }
+}
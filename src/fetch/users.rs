@@ -1,27 +1,120 @@
 use color_eyre::{
-    eyre::{bail, eyre, Context},
-    Result,
+    eyre::{eyre, Context},
+    Report,
 };
-use fantoccini::{error::CmdError, Client, Locator};
+use fantoccini::{error::CmdError, Locator};
 use indexmap::IndexSet;
 use scraper::{Html, Selector};
+use std::{fmt, str::FromStr};
 use tracing::{debug, info, span, warn, Level, Span};
 
+use super::error::{FetchError, FetchErrorKind, FetchResult};
+use super::login::check_login_wall;
 use crate::config::Config;
+use crate::driver_pool::WrappedClient;
 use crate::utils::{has_classes, sleep_secs};
+use crate::wait;
 
 #[derive(Debug, Clone)]
 pub struct FetchedUser {
-    display_name: String,
-    username: String,
-    description: String,
-    date_created: String,
-    related_link: Option<String>,
-    location: Option<String>,
-    following: usize,
-    followers: usize,
-    pfp_url: String,
-    banner_url: String,
+    pub display_name: String,
+    pub username: String,
+    pub description: String,
+    pub date_created: String,
+    pub related_link: Option<String>,
+    pub location: Option<String>,
+    pub following: usize,
+    pub followers: usize,
+    pub pfp_url: String,
+    pub banner_url: String,
+    pub protected: bool,
+}
+
+impl FetchedUser {
+    pub fn status(&self) -> AccountStatus {
+        if self.protected {
+            AccountStatus::Protected
+        } else {
+            AccountStatus::Active
+        }
+    }
+}
+
+/// What can be seen of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Active,
+    /// Only followers can see its posts
+    Protected,
+    Suspended,
+    /// Either deactivated, or it never existed
+    NotFound,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Protected => "protected",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::NotFound => "not_found",
+        }
+    }
+
+    /// The status of an account that failed to be fetched with `e`, if the
+    /// error says anything about it.
+    pub fn from_error(e: &FetchError) -> Option<Self> {
+        match e.kind() {
+            FetchErrorKind::Protected => Some(AccountStatus::Protected),
+            FetchErrorKind::Suspended => Some(AccountStatus::Suspended),
+            FetchErrorKind::NotFound => Some(AccountStatus::NotFound),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "active" => AccountStatus::Active,
+            "protected" => AccountStatus::Protected,
+            "suspended" => AccountStatus::Suspended,
+            "not_found" => AccountStatus::NotFound,
+            _ => return Err(eyre!("Unknown account status `{s}`")),
+        })
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Protected => "protected",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::NotFound => "deactivated or deleted",
+        })
+    }
+}
+
+/// Text of the message a profile shows in place of its posts, if any. Only
+/// this is checked for the profile's state, as anything else in the page can
+/// be written by the user.
+fn empty_state(src: &str) -> Option<String> {
+    let doc = Html::parse_document(src);
+    let selector = &Selector::parse("[data-testid=emptyState]").unwrap();
+    let state = doc.select(selector).next()?;
+    Some(state.text().collect())
+}
+
+/// Whether the profile in `src` only shows its posts to followers.
+pub(super) fn is_protected(src: &str) -> bool {
+    empty_state(src)
+        .map(|s| {
+            s.contains("These posts are protected") || s.contains("These Tweets are protected")
+        })
+        .unwrap_or(false)
 }
 
 mod json {
@@ -191,7 +284,7 @@ mod page {
         pub followers: Option<usize>,
     }
 
-    pub fn try_get_info_from_page(_user: &str, src: &str) -> Result<PageUserInfo> {
+    pub fn try_get_info_from_page(_user: &str, src: &str) -> FetchResult<PageUserInfo> {
         let doc = Html::parse_document(src);
         let div_selector = &Selector::parse("div").unwrap();
         let anchor_selector = &Selector::parse("a").unwrap();
@@ -203,7 +296,7 @@ mod page {
                     .map(|s| s == "UserName")
                     .unwrap_or(false)
             })
-            .ok_or(eyre!("Failed to find username"))?;
+            .ok_or(FetchError::layout_changed("div[data-testid=UserName]"))?;
 
         let text = username_div.text().collect::<String>();
         let mut iter = text.split('@').map(|s| s.trim().to_owned());
@@ -220,7 +313,9 @@ mod page {
                     .map(|s| s == "UserDescription")
                     .unwrap_or(false)
             })
-            .ok_or(eyre!("Failed to find user description"))?;
+            .ok_or(FetchError::layout_changed(
+                "div[data-testid=UserDescription]",
+            ))?;
 
         let _description = description_div.text().collect::<String>();
 
@@ -231,9 +326,9 @@ mod page {
                 .unwrap_or(false)
         });
 
-        let a = following_anchor.next().ok_or(eyre!(
-            "No element with link `following` to extract following count"
-        ))?;
+        let a = following_anchor
+            .next()
+            .ok_or(FetchError::layout_changed("a[href*=following]"))?;
         let text = a.text().collect::<String>();
         let following = text
             .split_whitespace()
@@ -257,9 +352,9 @@ mod page {
                 .unwrap_or(false)
         });
 
-        let a = followers_anchor.next().ok_or(eyre!(
-            "No element with link `follower` to extract followers count"
-        ))?;
+        let a = followers_anchor
+            .next()
+            .ok_or(FetchError::layout_changed("a[href*=followers]"))?;
         let text = a.text().collect::<String>();
         let followers = text
             .split_whitespace()
@@ -277,42 +372,70 @@ mod page {
         debug!(_following);
         debug!(_followers);
 
-        bail!("TODO: Unimplemented")
+        Err(eyre!("TODO: Unimplemented").into())
     }
 }
 
-async fn goto_user_profile(c: &Client, user_link: &str) -> Result<()> {
+/// Errors if the profile can't be shown at all.
+fn check_profile_available(src: &str) -> FetchResult<()> {
+    let Some(state) = empty_state(src) else {
+        return Ok(());
+    };
+    if state.contains("This account doesn’t exist") || state.contains("This account doesn't exist")
+    {
+        Err(FetchError::NotFound)
+    } else if state.contains("Account suspended") {
+        Err(FetchError::Suspended)
+    } else {
+        Ok(())
+    }
+}
+
+async fn goto_user_profile(c: &WrappedClient, user_link: &str) -> FetchResult<()> {
     c.goto(user_link).await?;
-    sleep_secs(4).await;
+    check_login_wall(c).await?;
+    check_profile_available(&c.source().await?)?;
     // Find "Yes, view profile" button for NSFW profiles
     match c.find(Locator::XPath("/html/body/div[1]/div/div/div[2]/main/div/div/div/div/div/div[3]/div/div/div[2]/div/div[3]/div")).await {
-        Ok(e) => e.click().await?,
+        Ok(e) => {
+            e.click().await?;
+            wait::for_network_idle(c).await?;
+        }
         Err(CmdError::NoSuchElement(_)) => {}
         Err(e) => return Err(e.into()),
     };
-    sleep_secs(4).await;
 
     Ok(())
 }
 
-fn get_banner_url_impl(src: &str) -> Result<String> {
+fn get_banner_url_impl(src: &str) -> FetchResult<String> {
     let doc = Html::parse_document(src);
     let img_selector = &Selector::parse("img").unwrap();
-    doc.select(img_selector)
+    let img = doc
+        .select(img_selector)
         .find(|i| i.value().attr("alt").map(|s| s == "Image").unwrap_or(false))
-        .ok_or(eyre!("Failed to find banner in page"))
-        .map(|i| i.value().attr("src").ok_or(eyre!("Image had no src attr")))
-        .and_then(|v| v)
-        .map(|s| s.to_owned())
+        .ok_or(FetchError::layout_changed("img[alt=Image]"))?;
+    Ok(img
+        .value()
+        .attr("src")
+        .ok_or(FetchError::layout_changed("img[alt=Image][src]"))?
+        .to_owned())
 }
 
-async fn get_banner_url(c: &Client, user_link: &str, config: &Config) -> Result<String> {
+async fn get_banner_url(
+    c: &WrappedClient,
+    user_link: &str,
+    config: &Config,
+) -> FetchResult<String> {
     // Click on the banner
-    c.find(Locator::XPath(config.twitter_config.xpath("banner_img")?))
-        .await?
-        .click()
-        .await?;
-    sleep_secs(5).await;
+    wait::for_element(
+        c,
+        Locator::XPath(config.twitter_config.xpath("banner_img")?),
+    )
+    .await?
+    .click()
+    .await?;
+    wait::for_element(c, Locator::Css("img[alt=\"Image\"]")).await?;
 
     let src = c.source().await?;
     let res = get_banner_url_impl(&src);
@@ -329,16 +452,16 @@ async fn get_banner_url(c: &Client, user_link: &str, config: &Config) -> Result<
 }
 
 pub async fn get_user_info(
-    c: &Client,
+    c: &WrappedClient,
     user: &str,
     user_link: &str,
     config: &Config,
-) -> Result<FetchedUser> {
-    // TODO: Retry maybe?
+) -> FetchResult<FetchedUser> {
     goto_user_profile(c, user_link).await?;
 
     let span = span!(Level::INFO, "info_from_json");
     let src = c.source().await?;
+    let protected = is_protected(&src);
     if let Some(u) = json::try_get_info_from_json(span, &src) {
         info!("Got user info for {user} from json");
         let banner_url = get_banner_url(c, user_link, config)
@@ -356,6 +479,7 @@ pub async fn get_user_info(
             followers: u.followers,
             pfp_url: u.pfp_url,
             banner_url,
+            protected,
         });
     } else {
         warn!("Failed getting user info for {user} from json");
@@ -372,16 +496,36 @@ pub async fn get_user_info(
 
     c.find(Locator::XPath("/html/body/div[1]/div/div/div[2]/main/div/div/div/div/div/div[3]/div/div/div/div/div[1]/div[1]")).await?;
 
-    bail!("TODO: Getting user info not implemented yet")
+    Err(eyre!("TODO: Getting user info not implemented yet").into())
 }
 
-pub async fn get_users_from_following(c: &Client, config: &Config) -> Result<Vec<String>> {
-    c.goto(&format!(
-        "https://twitter.com/{user}/following",
-        user = config.fetch_config.fetch_username
-    ))
-    .await?;
-    sleep_secs(6).await;
+/// Fetches the users followed by `seed`.
+pub async fn get_users_from_following(
+    c: &WrappedClient,
+    seed: &str,
+    config: &Config,
+) -> FetchResult<Vec<String>> {
+    c.goto(&format!("https://twitter.com/{seed}/following"))
+        .await?;
+    check_login_wall(c).await?;
+    collect_users(c, config, &format!("following of {seed}")).await
+}
+
+/// Fetches the members of the list with `list_id`.
+pub async fn get_list_members(
+    c: &WrappedClient,
+    list_id: &str,
+    config: &Config,
+) -> FetchResult<Vec<String>> {
+    c.goto(&format!("https://twitter.com/i/lists/{list_id}/members"))
+        .await?;
+    check_login_wall(c).await?;
+    collect_users(c, config, &format!("members of list {list_id}")).await
+}
+
+/// Scrolls through the list of users in the page until no more show up,
+/// collecting their usernames. `what` says what the list is, for logging.
+async fn collect_users(c: &WrappedClient, config: &Config, what: &str) -> FetchResult<Vec<String>> {
     let anchor_selector = &Selector::parse("a").unwrap();
     let following_users_classes = config.twitter_config.css_class("following_users")?;
     let mut users = IndexSet::new();
@@ -389,10 +533,11 @@ pub async fn get_users_from_following(c: &Client, config: &Config) -> Result<Vec
     let mut retries = 0;
     let max_retries = config.fetch_config.max_retries;
     while retries < max_retries {
-        c.execute("window.scrollBy(0,100);", vec![]).await?;
-        sleep_secs(config.fetch_config.users_from_following_retry_delay * (retries + 1)).await;
+        c.scroll_by(100).await?;
         if retries != 0 {
-            info!("{retries}/{max_retries} retries at fetching users from following");
+            info!("{retries}/{max_retries} retries at fetching users from {what}");
+            // More users might still be on the way, even if nothing is loading
+            sleep_secs(config.fetch_config.users_from_following_retry_delay * retries).await;
         }
 
         let s = c.source().await?;
@@ -417,7 +562,44 @@ pub async fn get_users_from_following(c: &Client, config: &Config) -> Result<Vec
         debug!("Got {} users so far", users.len());
     }
 
-    info!("Ended searching with {} users", users.len());
+    info!("Ended searching {what} with {} users", users.len());
 
     Ok(users.into_iter().collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(bio: &str, empty_state: Option<&str>) -> String {
+        let state = empty_state
+            .map(|s| format!(r#"<div data-testid="emptyState"><span>{s}</span></div>"#))
+            .unwrap_or_default();
+        format!(
+            r#"<html><body><div data-testid="UserDescription">{bio}</div>{state}</body></html>"#
+        )
+    }
+
+    #[test]
+    fn profile_state_comes_from_the_empty_state() {
+        assert!(matches!(
+            check_profile_available(&profile("", Some("Account suspended"))),
+            Err(FetchError::Suspended)
+        ));
+        assert!(matches!(
+            check_profile_available(&profile("", Some("This account doesn’t exist"))),
+            Err(FetchError::NotFound)
+        ));
+        assert!(is_protected(&profile(
+            "",
+            Some("These posts are protected")
+        )));
+    }
+
+    #[test]
+    fn profile_state_ignores_what_the_user_wrote() {
+        let src = profile("Account suspended? These posts are protected!", None);
+        assert!(check_profile_available(&src).is_ok());
+        assert!(!is_protected(&src));
+    }
+}
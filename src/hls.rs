@@ -0,0 +1,573 @@
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use hyper::{body::HttpBody, client::HttpConnector, Body, Client, StatusCode};
+use hyper_rustls::HttpsConnector;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{fs, io::AsyncWriteExt, process::Command};
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// How many times a segment is tried before giving up on the download.
+const SEGMENT_ATTEMPTS: usize = 3;
+/// How long a request can take, body included, so a stalled server doesn't
+/// hang the download.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>, Body>;
+
+pub fn http_client() -> HttpClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/// A version of the stream, from a master playlist.
+struct Variant {
+    bandwidth: u64,
+    url: Url,
+    /// Group of the audio renditions to play along, if the audio is separate
+    audio: Option<String>,
+}
+
+struct MediaPlaylist {
+    /// Initialization section, for fMP4 segments
+    init: Option<Url>,
+    segments: Vec<Url>,
+}
+
+impl MediaPlaylist {
+    fn extension(&self) -> &'static str {
+        if self.init.is_some() {
+            "mp4"
+        } else {
+            "ts"
+        }
+    }
+}
+
+/// Parses an attribute list, i.e. `BANDWIDTH=1000,CODECS="avc1,mp4a"`.
+fn parse_attributes(s: &str) -> HashMap<&str, &str> {
+    let mut attributes = HashMap::new();
+    let mut rest = s;
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (
+                &quoted[..end],
+                quoted[end..]
+                    .trim_start_matches('"')
+                    .trim_start_matches(','),
+            )
+        } else {
+            value.split_once(',').unwrap_or((value, ""))
+        };
+        attributes.insert(key.trim(), value);
+        rest = next;
+    }
+    attributes
+}
+
+fn is_master(playlist: &str) -> bool {
+    playlist.contains("#EXT-X-STREAM-INF")
+}
+
+/// Parses a master playlist into its variants, and its audio renditions by
+/// group.
+fn parse_master(playlist: &str, base: &Url) -> Result<(Vec<Variant>, HashMap<String, Url>)> {
+    let mut variants = vec![];
+    let mut audio = HashMap::new();
+    let mut lines = playlist.lines().map(|l| l.trim());
+    while let Some(line) = lines.next() {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let attributes = parse_attributes(attributes);
+            let uri = lines
+                .by_ref()
+                .find(|l| !l.is_empty() && !l.starts_with('#'))
+                .ok_or(eyre!("Variant has no URI"))?;
+            variants.push(Variant {
+                bandwidth: attributes
+                    .get("BANDWIDTH")
+                    .and_then(|b| b.parse().ok())
+                    .unwrap_or(0),
+                url: base.join(uri)?,
+                audio: attributes.get("AUDIO").map(|a| a.to_string()),
+            });
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attributes = parse_attributes(attributes);
+            if let (Some(&"AUDIO"), Some(group), Some(uri)) = (
+                attributes.get("TYPE"),
+                attributes.get("GROUP-ID"),
+                attributes.get("URI"),
+            ) {
+                // Prefer the default rendition of each group
+                if !audio.contains_key(*group) || attributes.get("DEFAULT") == Some(&"YES") {
+                    audio.insert(group.to_string(), base.join(uri)?);
+                }
+            }
+        }
+    }
+    Ok((variants, audio))
+}
+
+fn parse_media(playlist: &str, base: &Url) -> Result<MediaPlaylist> {
+    let mut init = None;
+    let mut segments = vec![];
+    for line in playlist.lines().map(|l| l.trim()) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+            let uri = parse_attributes(attributes)
+                .get("URI")
+                .copied()
+                .ok_or(eyre!("EXT-X-MAP has no URI"))?;
+            init = Some(base.join(uri)?);
+        } else if line.starts_with("#EXT-X-KEY") && !line.contains("METHOD=NONE") {
+            bail!("Encrypted streams are not supported");
+        } else if !line.is_empty() && !line.starts_with('#') {
+            segments.push(base.join(line)?);
+        }
+    }
+    if segments.is_empty() {
+        bail!("Playlist has no segments");
+    }
+    Ok(MediaPlaylist { init, segments })
+}
+
+async fn get(client: &HttpClient, url: &Url) -> Result<Vec<u8>> {
+    let uri = url.as_str().parse()?;
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let mut res = client
+            .get(uri)
+            .await
+            .wrap_err_with(|| format!("Failed requesting {url}"))?;
+        if res.status() != StatusCode::OK {
+            bail!("Requesting {url} returned {}", res.status());
+        }
+        let mut body = vec![];
+        while let Some(chunk) = res.body_mut().data().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok(body)
+    })
+    .await
+    .map_err(|_| eyre!("Requesting {url} timed out"))?
+}
+
+async fn get_text(client: &HttpClient, url: &Url) -> Result<String> {
+    String::from_utf8(get(client, url).await?).wrap_err("Playlist is not UTF-8")
+}
+
+/// Writes `contents` to `path` through a temporary file, so an interrupted
+/// write doesn't leave a partial file behind.
+async fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Downloads every segment of `playlist` and joins them in `output`.
+/// Segments are kept next to it until then, so an interrupted download picks
+/// up where it left off.
+async fn download_stream(
+    client: &HttpClient,
+    playlist: &MediaPlaylist,
+    output: &Path,
+) -> Result<()> {
+    let parts_dir = output.with_extension("parts");
+    if fs::try_exists(output).await? && !fs::try_exists(&parts_dir).await? {
+        debug!("Already downloaded {}", output.display());
+        return Ok(());
+    }
+    fs::create_dir_all(&parts_dir)
+        .await
+        .wrap_err_with(|| format!("Failed creating {}", parts_dir.display()))?;
+
+    let urls = playlist.init.iter().chain(&playlist.segments);
+    let count = playlist.segments.len() + playlist.init.iter().count();
+    let mut parts = vec![];
+    for (i, url) in urls.enumerate() {
+        let part = parts_dir.join(format!("{i:05}"));
+        if fs::try_exists(&part).await? {
+            debug!("Already have segment {i} of {}", output.display());
+            parts.push(part);
+            continue;
+        }
+        let mut attempt = 1;
+        let data = loop {
+            match get(client, url).await {
+                Ok(data) => break data,
+                Err(e) if attempt < SEGMENT_ATTEMPTS => {
+                    warn!("Failed getting segment {i}, retrying: {e:#}");
+                    tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.wrap_err(format!("Failed getting segment {i}"))),
+            }
+        };
+        write_atomic(&part, &data).await?;
+        debug!("Got segment {}/{count} of {}", i + 1, output.display());
+        parts.push(part);
+    }
+
+    let tmp = output.with_extension("tmp");
+    let mut file = fs::File::create(&tmp).await?;
+    for part in &parts {
+        file.write_all(&fs::read(part).await?).await?;
+    }
+    file.flush().await?;
+    fs::rename(&tmp, output).await?;
+    fs::remove_dir_all(&parts_dir).await?;
+    Ok(())
+}
+
+async fn remux(ffmpeg: &str, inputs: &[&Path], output: &Path) -> Result<()> {
+    let mut command = Command::new(ffmpeg);
+    command.arg("-y").arg("-loglevel").arg("error");
+    for input in inputs {
+        command.arg("-i").arg(input);
+    }
+    for i in 0..inputs.len() {
+        command.arg("-map").arg(format!("{i}"));
+    }
+    let status = command
+        .arg("-c")
+        .arg("copy")
+        .arg(output)
+        .status()
+        .await
+        .wrap_err_with(|| format!("Failed running {ffmpeg}"))?;
+    if !status.success() {
+        bail!("{ffmpeg} exited with {status}");
+    }
+    Ok(())
+}
+
+/// Downloads the video at `playlist_url`, picking the highest bitrate if
+/// there are several, to `output` with the extension of its format. With
+/// `ffmpeg`, separate audio is muxed in, and the result is always an MP4.
+/// Without it, separate audio is left next to the video. Returns where the
+/// video ended up, without fetching anything if it's already there.
+pub async fn download(
+    client: &HttpClient,
+    playlist_url: &str,
+    output: &Path,
+    ffmpeg: Option<&str>,
+) -> Result<PathBuf> {
+    // The format is only known from the playlist, so any of them will do
+    for extension in ["mp4", "ts"] {
+        let path = output.with_extension(extension);
+        if fs::try_exists(&path).await? {
+            debug!("Already downloaded {}", path.display());
+            return Ok(path);
+        }
+    }
+    let url = Url::parse(playlist_url)?;
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    let playlist = get_text(client, &url).await?;
+    let (video_url, audio_url, playlist) = if is_master(&playlist) {
+        let (variants, audio) = parse_master(&playlist, &url)?;
+        let best = variants
+            .into_iter()
+            .max_by_key(|v| v.bandwidth)
+            .ok_or(eyre!("Master playlist has no variants"))?;
+        debug!("Picked variant {} at {}bps", best.url, best.bandwidth);
+        let audio_url = best.audio.and_then(|group| audio.get(&group).cloned());
+        let playlist = get_text(client, &best.url).await?;
+        (best.url, audio_url, playlist)
+    } else {
+        (url, None, playlist)
+    };
+
+    let video = parse_media(&playlist, &video_url)?;
+    let video_path = output.with_extension(format!("video.{}", video.extension()));
+    download_stream(client, &video, &video_path).await?;
+    let audio_path = match audio_url {
+        Some(audio_url) => {
+            let audio = parse_media(&get_text(client, &audio_url).await?, &audio_url)?;
+            let audio_path = output.with_extension(format!("audio.{}", audio.extension()));
+            download_stream(client, &audio, &audio_path).await?;
+            Some(audio_path)
+        }
+        None => None,
+    };
+
+    let path = match (ffmpeg, audio_path) {
+        (Some(ffmpeg), audio_path) => {
+            let path = output.with_extension("mp4");
+            let mut inputs = vec![video_path.as_path()];
+            inputs.extend(audio_path.as_deref());
+            remux(ffmpeg, &inputs, &path).await?;
+            for input in inputs {
+                fs::remove_file(input).await?;
+            }
+            path
+        }
+        (None, audio_path) => {
+            if let Some(audio_path) = audio_path {
+                warn!(
+                    "No ffmpeg to mux audio with, it was left at {}",
+                    audio_path.display()
+                );
+            }
+            let path = output.with_extension(video.extension());
+            fs::rename(&video_path, &path).await?;
+            path
+        }
+    };
+    info!("Downloaded {playlist_url} to {}", path.display());
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    /// Serves `files` by path from a local port, counting the requests made.
+    /// Returns the URL it's served at.
+    async fn serve(files: Vec<(&'static str, &'static [u8])>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let files = Arc::new(files.into_iter().collect::<HashMap<_, _>>());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let files = Arc::clone(&files);
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut request = vec![];
+                    let mut buf = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split(' ').nth(1).unwrap_or("/");
+                    let (status, body) = match files.get(path) {
+                        Some(body) => ("200 OK", *body),
+                        None => ("404 Not Found", &b""[..]),
+                    };
+                    let head = format!(
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    );
+                    stream.write_all(head.as_bytes()).await.unwrap();
+                    stream.write_all(body).await.unwrap();
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    /// An empty directory to download to, named after the test.
+    async fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("twitarc-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        dir
+    }
+
+    const MASTER: &[u8] = b"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=256000,CODECS=\"avc1.4d401e,mp4a.40.2\",RESOLUTION=320x180
+low/video.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=832000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=640x360
+high/video.m3u8
+";
+
+    const MEDIA: &[u8] = b"#EXTM3U
+#EXT-X-TARGETDURATION:3
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:3.0,
+0.m4s
+#EXTINF:3.0,
+/segments/1.m4s
+#EXT-X-ENDLIST
+";
+
+    fn stream_files() -> Vec<(&'static str, &'static [u8])> {
+        vec![
+            ("/master.m3u8", MASTER),
+            ("/high/video.m3u8", MEDIA),
+            ("/high/init.mp4", b"init,"),
+            ("/high/0.m4s", b"first,"),
+            ("/segments/1.m4s", b"second"),
+        ]
+    }
+
+    #[tokio::test]
+    async fn downloaded_videos_are_not_fetched_again() {
+        let (url, requests) = serve(stream_files()).await;
+        let output = output_dir("downloaded-again").await.join("video");
+        let client = http_client();
+        let playlist_url = format!("{url}/master.m3u8");
+
+        let path = download(&client, &playlist_url, &output, None)
+            .await
+            .unwrap();
+        let fetched = requests.load(Ordering::SeqCst);
+        assert!(fetched > 0);
+        let again = download(&client, &playlist_url, &output, None)
+            .await
+            .unwrap();
+        assert_eq!(again, path);
+        assert_eq!(requests.load(Ordering::SeqCst), fetched);
+        fs::remove_dir_all(output.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_best_variant_is_downloaded() {
+        let (url, _) = serve(stream_files()).await;
+        let output = output_dir("best-variant").await.join("video");
+        let path = download(&http_client(), &format!("{url}/master.m3u8"), &output, None)
+            .await
+            .unwrap();
+        assert_eq!(path, output.with_extension("mp4"));
+        assert_eq!(fs::read(&path).await.unwrap(), b"init,first,second");
+        assert!(!fs::try_exists(output.with_extension("video.parts"))
+            .await
+            .unwrap());
+        fs::remove_dir_all(output.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn media_playlists_are_downloaded_directly() {
+        let (url, _) = serve(vec![
+            (
+                "/video.m3u8",
+                b"#EXTM3U\n#EXTINF:3.0,\na.ts\n#EXTINF:3.0,\nb.ts\n#EXT-X-ENDLIST\n",
+            ),
+            ("/a.ts", b"a"),
+            ("/b.ts", b"b"),
+        ])
+        .await;
+        let output = output_dir("media-playlist").await.join("video");
+        let path = download(&http_client(), &format!("{url}/video.m3u8"), &output, None)
+            .await
+            .unwrap();
+        assert_eq!(path, output.with_extension("ts"));
+        assert_eq!(fs::read(&path).await.unwrap(), b"ab");
+        fs::remove_dir_all(output.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn interrupted_downloads_resume_from_their_parts() {
+        let mut files = stream_files();
+        files.retain(|(path, _)| !matches!(*path, "/high/init.mp4" | "/high/0.m4s"));
+        let (url, requests) = serve(files).await;
+        let output = output_dir("resume").await.join("video");
+        let parts = output.with_extension("video.parts");
+        fs::create_dir_all(&parts).await.unwrap();
+        fs::write(parts.join("00000"), b"init,").await.unwrap();
+        fs::write(parts.join("00001"), b"kept,").await.unwrap();
+
+        let path = download(&http_client(), &format!("{url}/master.m3u8"), &output, None)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&path).await.unwrap(), b"init,kept,second");
+        // Both playlists, and the one missing segment
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(!fs::try_exists(&parts).await.unwrap());
+        fs::remove_dir_all(output.parent().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_segments_fail_the_download() {
+        let mut files = stream_files();
+        files.retain(|(path, _)| *path != "/segments/1.m4s");
+        let (url, _) = serve(files).await;
+        let output = output_dir("missing-segment").await.join("video");
+        let res = download(&http_client(), &format!("{url}/master.m3u8"), &output, None).await;
+        assert!(res.is_err());
+        assert!(!fs::try_exists(output.with_extension("mp4")).await.unwrap());
+        fs::remove_dir_all(output.parent().unwrap()).await.unwrap();
+    }
+
+    #[test]
+    fn quoted_attributes_keep_their_commas() {
+        let attributes = parse_attributes(
+            r#"BANDWIDTH=832000,CODECS="avc1.4d401f,mp4a.40.2",RESOLUTION=640x360,AUDIO="aud""#,
+        );
+        assert_eq!(attributes["BANDWIDTH"], "832000");
+        assert_eq!(attributes["CODECS"], "avc1.4d401f,mp4a.40.2");
+        assert_eq!(attributes["RESOLUTION"], "640x360");
+        assert_eq!(attributes["AUDIO"], "aud");
+        assert_eq!(attributes.len(), 4);
+    }
+
+    #[test]
+    fn master_playlists_resolve_relative_uris() {
+        let base = Url::parse("https://video.twimg.com/ext_tw_video/1/pu/pl/master.m3u8").unwrap();
+        let playlist = r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aud",NAME="Other",URI="/audio/other.m3u8"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="aud",NAME="Main",DEFAULT=YES,URI="audio/main.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=256000,CODECS="avc1.4d401e,mp4a.40.2",AUDIO="aud"
+low.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=832000,CODECS="avc1.4d401f,mp4a.40.2"
+
+https://other.example/high.m3u8
+"#;
+        let (variants, audio) = parse_master(playlist, &base).unwrap();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].bandwidth, 256000);
+        assert_eq!(
+            variants[0].url.as_str(),
+            "https://video.twimg.com/ext_tw_video/1/pu/pl/low.m3u8"
+        );
+        assert_eq!(variants[0].audio.as_deref(), Some("aud"));
+        assert_eq!(variants[1].url.as_str(), "https://other.example/high.m3u8");
+        assert_eq!(variants[1].audio, None);
+        assert_eq!(
+            audio["aud"].as_str(),
+            "https://video.twimg.com/ext_tw_video/1/pu/pl/audio/main.m3u8"
+        );
+    }
+
+    #[test]
+    fn media_playlists_have_their_init_section() {
+        let base = Url::parse("https://video.twimg.com/pl/avc1/640x360/video.m3u8").unwrap();
+        let playlist = std::str::from_utf8(MEDIA).unwrap();
+        let media = parse_media(playlist, &base).unwrap();
+        assert_eq!(
+            media.init.as_ref().map(|u| u.as_str()),
+            Some("https://video.twimg.com/pl/avc1/640x360/init.mp4")
+        );
+        let segments = media
+            .segments
+            .iter()
+            .map(|u| u.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            [
+                "https://video.twimg.com/pl/avc1/640x360/0.m4s",
+                "https://video.twimg.com/segments/1.m4s",
+            ]
+        );
+        assert_eq!(media.extension(), "mp4");
+    }
+
+    #[test]
+    fn encrypted_and_empty_playlists_are_rejected() {
+        let base = Url::parse("https://video.twimg.com/video.m3u8").unwrap();
+        let encrypted = "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\n#EXTINF:3.0,\na.ts\n";
+        assert!(parse_media(encrypted, &base).is_err());
+        assert!(parse_media("#EXTM3U\n#EXT-X-ENDLIST\n", &base).is_err());
+        let plain = "#EXTM3U\n#EXT-X-KEY:METHOD=NONE\n#EXTINF:3.0,\na.ts\n";
+        assert_eq!(parse_media(plain, &base).unwrap().extension(), "ts");
+    }
+}
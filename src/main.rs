@@ -1,44 +1,62 @@
 use color_eyre::{
-    eyre::{bail, eyre, Context, Result},
+    eyre::{bail, Context, Result},
     Report,
 };
-use std::sync::Arc;
+use indexmap::IndexMap;
+use std::{collections::HashMap, path::Path, sync::Arc};
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 mod client;
 mod config;
+mod db;
 mod driver_pool;
+mod feed;
 mod fetch;
+mod hls;
+mod rate_limit;
+mod secrets;
 mod utils;
+mod wait;
 
-use config::Config;
-use driver_pool::DriverPool;
+use config::{Command, Config};
+use db::{Db, Source, TrackReason};
+use driver_pool::{ClientAuth, DriverPool, WrappedClient};
 
-use crate::fetch::users::{get_user_info, get_users_from_following};
+use crate::feed::{
+    posts::{write_likes_feeds, write_list_feeds, write_search_feeds, write_user_feeds},
+    status::write_status_feed,
+};
+use crate::fetch::error::{FetchError, FetchErrorKind};
+use crate::fetch::post::{
+    get_post, get_posts_from_list, get_posts_from_search, get_recent_posts_from_user,
+    split_status_link, FetchedPost,
+};
+use crate::fetch::users::{
+    get_list_members, get_user_info, get_users_from_following, AccountStatus, FetchedUser,
+};
 use crate::utils::get_user_link;
 
-async fn run(pool: Arc<DriverPool>, config: Config) -> Result<()> {
-    let client = pool
-        .get_client(&config.twitter_config)
-        .await
-        .wrap_err("Could not get client")?
-        .ok_or(eyre!("No clients available!"))?;
+/// A user waiting to be fetched.
+struct QueuedUser {
+    name: String,
+    /// How many times fetching this user failed
+    attempts: usize,
+    /// Failed users are only retried after some time
+    not_before: Instant,
+}
 
-    let users = match get_users_from_following(&client, &config)
-        .await
-        .wrap_err("Failed getting users")
-    {
-        Ok(users) => users,
-        Err(e) => {
-            client.close().await?;
-            return Err(e);
-        }
+async fn run(pool: Arc<DriverPool>, db: Arc<Db>, config: Config) -> Result<()> {
+    let auth = if config.fetch_config.guest_mode {
+        ClientAuth::Guest
+    } else {
+        ClientAuth::LoggedIn
     };
 
-    // TODO: Maybe do the user list fetch at the same time as the users
-    // list is starting to be fetched
-    client.close().await?;
+    let users = resolve_users(&pool, &db, &config, auth)
+        .await
+        .wrap_err("Failed getting users")?;
 
     let max_concurrent_users = config.fetch_config.max_concurrent_users;
     let (user_tx, user_rx) = async_channel::unbounded();
@@ -48,10 +66,18 @@ async fn run(pool: Arc<DriverPool>, config: Config) -> Result<()> {
     }
 
     let config = Arc::new(config);
-    for user in users {
-        user_tx.send(user).await?;
+    let user_count = users.len();
+    for name in users {
+        user_tx
+            .send(QueuedUser {
+                name,
+                attempts: 0,
+                not_before: Instant::now(),
+            })
+            .await?;
     }
 
+    let http = hls::http_client();
     let mut tasks = vec![];
     info!(
         "Starting {} tasks to fetch users",
@@ -59,19 +85,28 @@ async fn run(pool: Arc<DriverPool>, config: Config) -> Result<()> {
     );
     for i in 0..config.fetch_config.max_concurrent_users {
         let user_rx = rxs.pop().unwrap();
+        let user_tx = user_tx.clone();
         let pool = Arc::clone(&pool);
+        let db = Arc::clone(&db);
         let config = Arc::clone(&config);
+        let http = http.clone();
         let handle = tokio::spawn(async move {
             let id = i;
             debug!("Started user fetch task {id}");
-            let c = pool
-                .get_client(&config.twitter_config)
-                .await
-                .wrap_err("Failed getting a client to download users")?;
-            let c = c.ok_or(eyre!("Failed getting a client to download users, even thought there should be some available"))?;
-            debug!("Successfully got client in task {id}");
+            // Swapped for a logged in one when a user needs it, when
+            // fetching as a guest
+            let mut client: Option<WrappedClient> = None;
+            let mut failed = vec![];
+            // Users put back in a row as they weren't due yet, and when the
+            // first of them is
+            let mut put_back = 0;
+            let mut waiting_until: Option<Instant> = None;
             loop {
-                let user = match user_rx.try_recv() {
+                let QueuedUser {
+                    name: user,
+                    attempts,
+                    not_before,
+                } = match user_rx.try_recv() {
                     Ok(u) => u,
                     Err(e) => match e {
                         async_channel::TryRecvError::Closed => bail!("Channel closed unexpectedly"),
@@ -82,29 +117,594 @@ async fn run(pool: Arc<DriverPool>, config: Config) -> Result<()> {
                     },
                 };
                 debug!("Received user {user} in task {id}");
+                if not_before > Instant::now() {
+                    user_tx
+                        .send(QueuedUser {
+                            name: user,
+                            attempts,
+                            not_before,
+                        })
+                        .await?;
+                    put_back += 1;
+                    let earliest = waiting_until.map_or(not_before, |w| w.min(not_before));
+                    waiting_until = Some(earliest);
+                    // Every queued user is waiting to be retried, so wait for
+                    // the first one without holding on to a driver
+                    if put_back > user_rx.len() {
+                        if let Some(c) = client.take() {
+                            c.close().await?;
+                        }
+                        tokio::time::sleep_until(earliest).await;
+                        put_back = 0;
+                        waiting_until = None;
+                    }
+                    continue;
+                }
+                put_back = 0;
+                waiting_until = None;
+                let c = match client.take() {
+                    Some(c) if c.auth() == auth => c,
+                    other => {
+                        if let Some(c) = other {
+                            c.close().await?;
+                        }
+                        let c = pool
+                            .wait_for_client(&config.twitter_config, auth)
+                            .await
+                            .wrap_err("Failed getting a client to download users")?;
+                        debug!("Successfully got client in task {id}");
+                        c
+                    }
+                };
+                let c = &*client.insert(c);
                 let user_link = get_user_link(&user);
-                let user_info = match get_user_info(&c, &user, &user_link, &config).await {
+                let mut user_info = get_user_info(c, &user, &user_link, &config).await;
+                if c.auth() == ClientAuth::Guest
+                    && matches!(&user_info, Err(FetchError::LoginRequired))
+                {
+                    debug!("{user} requires logging in, retrying with a logged in client");
+                    match log_in(&pool, &config, &mut client).await {
+                        Ok(lc) => user_info = get_user_info(lc, &user, &user_link, &config).await,
+                        Err(e) => warn!("No logged in client to fetch {user} with: {e:#}"),
+                    }
+                }
+                let user_info = match user_info {
                     Ok(u) => u,
                     Err(e) => {
-                        warn!("Encountered error while fetching user info for {user}: {e:#}");
+                        // The account itself can't be fetched, which isn't a
+                        // failure worth retrying
+                        if let Some(status) = AccountStatus::from_error(&e) {
+                            info!("{user} is {status}");
+                            match db.set_user_status(&user, status).await {
+                                Ok(Some(change)) => {
+                                    info!("{user} went from {} to {status}", change.old_status)
+                                }
+                                Ok(None) => {}
+                                Err(db_e) => {
+                                    error!("Failed recording that {user} is {status}: {db_e:#}")
+                                }
+                            }
+                            continue;
+                        }
+                        let attempts = attempts + 1;
+                        let retry = &config.fetch_config.retry;
+                        if retry.should_retry(&e, attempts) {
+                            let delay = retry.backoff(attempts);
+                            warn!("Encountered error while fetching user info for {user}, retrying in {delay:?}: {e:#}");
+                            user_tx
+                                .send(QueuedUser {
+                                    name: user,
+                                    attempts,
+                                    not_before: Instant::now() + delay,
+                                })
+                                .await?;
+                        } else {
+                            error!("Giving up on fetching user info for {user} after {attempts} attempts: {e:#}");
+                            // Losing the record is better than losing every
+                            // user still queued for this task
+                            if let Err(db_e) = db.save_user_error(&user, &e).await {
+                                error!("Failed recording the error for {user}: {db_e:#}");
+                            }
+                            failed.push((user, e));
+                        }
                         continue;
                     }
                 };
-                info!("{user_info:#?}");
-                //let _posts = get_recent_posts_from_user(&c, &user_link, &config).await?;
+                // One user failing shouldn't lose the others queued here
+                let res =
+                    archive_user(&pool, &mut client, &http, &db, &config, &user, &user_info).await;
+                if let Err(e) = res {
+                    error!("Failed archiving {user}: {e:#}");
+                    failed.push((user, e.into()));
+                }
+            }
+            if let Some(c) = client {
+                c.close().await?;
             }
-            c.close().await?;
-            Ok::<(), Report>(())
+            Ok::<_, Report>(failed)
         });
         tasks.push(handle);
     }
+    let mut failed = vec![];
     for task in tasks {
         let task = task.await?;
-        if let Err(e) = task {
-            error!("Task encountered an error: {e:#}",);
+        match task {
+            Ok(f) => failed.extend(f),
+            Err(e) => error!("Task encountered an error: {e:#}",),
         }
     }
 
+    info!(
+        "Finished fetching {} users, {} failed",
+        user_count - failed.len(),
+        failed.len()
+    );
+    for (user, e) in &failed {
+        warn!(
+            "Failed fetching {user} ({kind}): {e:#}",
+            kind = e.kind().as_str()
+        );
+    }
+
+    archive_searches_and_lists(&pool, &http, &db, &config)
+        .await
+        .wrap_err("Failed archiving searches and lists")?;
+
+    verify_posts(&pool, &db, &config, auth)
+        .await
+        .wrap_err("Failed verifying posts")?;
+
+    write_status_feed(&db, &config)
+        .await
+        .wrap_err("Failed writing account status feed")?;
+    write_user_feeds(&db, &config)
+        .await
+        .wrap_err("Failed writing user feeds")?;
+    write_likes_feeds(&db, &config)
+        .await
+        .wrap_err("Failed writing likes feeds")?;
+    write_search_feeds(&db, &config)
+        .await
+        .wrap_err("Failed writing search feeds")?;
+    write_list_feeds(&db, &config)
+        .await
+        .wrap_err("Failed writing list feeds")?;
+
+    Ok(())
+}
+
+/// Swaps `client` for a logged in one, for pages guests can't see. The old one
+/// is given back first, so this can't wait on a driver every task is holding
+/// on to.
+async fn log_in<'a>(
+    pool: &DriverPool,
+    config: &Config,
+    client: &'a mut Option<WrappedClient>,
+) -> Result<&'a WrappedClient> {
+    if let Some(c) = client.take() {
+        c.close().await?;
+    }
+    let c = pool
+        .wait_for_client(&config.twitter_config, ClientAuth::LoggedIn)
+        .await?;
+    Ok(client.insert(c))
+}
+
+/// Saves `user_info` and the status of `user`, and archives the posts on each
+/// of their tabs if they can be seen, with the client it was fetched with in
+/// `client`. Tabs guests can't see are retried logged in.
+async fn archive_user(
+    pool: &DriverPool,
+    client: &mut Option<WrappedClient>,
+    http: &hls::HttpClient,
+    db: &Db,
+    config: &Config,
+    user: &str,
+    user_info: &FetchedUser,
+) -> Result<()> {
+    info!("{user_info:#?}");
+    db.save_user(user, user_info).await?;
+    let status = user_info.status();
+    if let Some(change) = db.set_user_status(user, status).await? {
+        info!("{user} went from {} to {status}", change.old_status);
+    }
+    if status != AccountStatus::Active {
+        debug!("Not fetching posts from {user}, as it is {status}");
+        return Ok(());
+    }
+    for &timeline in config.fetch_config.timelines_for(user) {
+        let source = Source::Timeline { user, timeline };
+        let archived = db.get_archived(source).await?;
+        let c = client.as_ref().expect("User was fetched without a client");
+        let guest = c.auth() == ClientAuth::Guest;
+        let mut posts = get_recent_posts_from_user(c, user, timeline, config, &archived).await;
+        if guest && matches!(&posts, Err(e) if e.kind() == FetchErrorKind::LoginRequired) {
+            debug!(
+                "The {} tab of {user} requires logging in, retrying with a logged in client",
+                timeline.as_str()
+            );
+            let c = log_in(pool, config, client)
+                .await
+                .wrap_err("No logged in client to fetch with")?;
+            posts = get_recent_posts_from_user(c, user, timeline, config, &archived).await;
+        }
+        let c = client.as_ref().unwrap();
+        match posts {
+            Ok(posts) => save_posts(c, http, db, config, source, posts).await?,
+            Err(FetchError::Protected) => {
+                db.set_user_status(user, AccountStatus::Protected).await?;
+                break;
+            }
+            Err(e) => warn!("Failed fetching {} of {user}: {e:#}", timeline.as_str()),
+        }
+    }
+    Ok(())
+}
+
+/// Archives `posts` seen on `source`, and the posts they quote.
+async fn save_posts(
+    c: &WrappedClient,
+    http: &hls::HttpClient,
+    db: &Db,
+    config: &Config,
+    source: Source<'_>,
+    mut posts: Vec<FetchedPost>,
+) -> Result<()> {
+    for post in &mut posts {
+        if config.storage_config.download_videos {
+            download_videos(http, post, config).await;
+        }
+        db.save_post(post).await?;
+        db.tag_post(&post.id, source).await?;
+        save_screenshot(db, post, config).await?;
+    }
+    // Quoted posts don't change, so they're only fetched once
+    for post in &posts {
+        let (Some(link), Some(id)) = (&post.quote_link, post.quote_id()) else {
+            continue;
+        };
+        if db.has_post(id).await? {
+            continue;
+        }
+        match get_post(c, link, config.storage_config.screenshots, &[]).await {
+            Ok(quote) => {
+                db.save_post(&quote).await?;
+                save_screenshot(db, &quote, config).await?;
+            }
+            Err(e) => warn!("Failed fetching {link}, quoted by {}: {e:#}", post.link),
+        }
+    }
+    Ok(())
+}
+
+/// Downloads the streamed videos in `post` to the media store. Failures are
+/// only logged, and tried again the next time the post is fetched.
+async fn download_videos(http: &hls::HttpClient, post: &mut FetchedPost, config: &Config) {
+    let storage = &config.storage_config;
+    for (i, media) in post.media.iter_mut().enumerate() {
+        let Some(playlist_url) = &media.playlist_url else {
+            continue;
+        };
+        let output = Path::new(&storage.media_dir)
+            .join(&post.username)
+            .join(format!("{}-{i}", post.id));
+        match hls::download(http, playlist_url, &output, storage.ffmpeg.as_deref()).await {
+            Ok(path) => media.path = Some(path.to_string_lossy().into_owned()),
+            Err(e) => warn!("Failed downloading video from {}: {e:#}", post.link),
+        }
+    }
+}
+
+/// Saves the screenshot taken of `post` to the media store, and records where
+/// it is. Failing to save it is only logged, and the post stays archived
+/// without one.
+async fn save_screenshot(db: &Db, post: &FetchedPost, config: &Config) -> Result<()> {
+    let Some(png) = &post.screenshot else {
+        return Ok(());
+    };
+    let path = Path::new(&config.storage_config.media_dir)
+        .join(&post.username)
+        .join(format!("{}.png", post.id));
+    let written = async {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, png).await
+    };
+    if let Err(e) = written.await {
+        warn!(
+            "Failed saving screenshot of {} to {}: {e}",
+            post.link,
+            path.display()
+        );
+        return Ok(());
+    }
+    db.set_post_screenshot(&post.id, &path.to_string_lossy())
+        .await
+}
+
+/// Users followed by `seed`, retrying with a logged in client if guests
+/// can't see them.
+async fn get_seed_following(
+    pool: &DriverPool,
+    config: &Config,
+    auth: ClientAuth,
+    seed: &str,
+) -> Result<Vec<String>> {
+    let client = pool
+        .wait_for_client(&config.twitter_config, auth)
+        .await
+        .wrap_err("Could not get client")?;
+
+    let users = match get_users_from_following(&client, seed, config).await {
+        Err(FetchError::LoginRequired) if auth == ClientAuth::Guest => {
+            info!("Fetching users requires logging in, retrying with a logged in client");
+            client.close().await?;
+            let client = pool
+                .wait_for_client(&config.twitter_config, ClientAuth::LoggedIn)
+                .await
+                .wrap_err("Could not get client")?;
+            let res = get_users_from_following(&client, seed, config).await;
+            client.close().await?;
+            res
+        }
+        res => {
+            // TODO: Maybe do the user list fetch at the same time as the users
+            // list is starting to be fetched
+            client.close().await?;
+            res
+        }
+    };
+    Ok(users?)
+}
+
+/// Members of every list in the config whose members are followed, with the
+/// list they're in. Lists require logging in.
+async fn get_list_users(pool: &DriverPool, config: &Config) -> Result<Vec<(String, TrackReason)>> {
+    let lists = config
+        .lists
+        .iter()
+        .filter(|l| l.members)
+        .collect::<Vec<_>>();
+    if lists.is_empty() {
+        return Ok(vec![]);
+    }
+    let c = pool
+        .wait_for_client(&config.twitter_config, ClientAuth::LoggedIn)
+        .await
+        .wrap_err("Could not get client")?;
+    let mut users = vec![];
+    let mut failed = 0;
+    for list in &lists {
+        match get_list_members(&c, &list.id, config).await {
+            Ok(members) => users.extend(members.into_iter().map(|m| {
+                (
+                    m,
+                    TrackReason::ListMember {
+                        list: list.name.clone(),
+                    },
+                )
+            })),
+            Err(e) => {
+                warn!("Failed getting members of list {}: {e:#}", list.name);
+                failed += 1;
+            }
+        }
+    }
+    c.close().await?;
+    if failed == lists.len() {
+        bail!("Failed getting members of every list");
+    }
+    Ok(users)
+}
+
+/// Works out which users to archive, from the followings of the seeds, the
+/// members of lists and the users in the config, leaving out excluded ones.
+/// Why each user is archived is saved. Fails only if every seed and list did.
+async fn resolve_users(
+    pool: &DriverPool,
+    db: &Db,
+    config: &Config,
+    auth: ClientAuth,
+) -> Result<Vec<String>> {
+    let fetch = &config.fetch_config;
+    let mut found = vec![];
+    // A seed that was renamed or suspended shouldn't stop the others
+    let mut failed = 0;
+    for seed in &fetch.seeds {
+        match get_seed_following(pool, config, auth, seed).await {
+            Ok(users) => found.extend(users.into_iter().map(|u| {
+                (
+                    u,
+                    TrackReason::Followed {
+                        by: seed.to_owned(),
+                    },
+                )
+            })),
+            Err(e) => {
+                warn!("Failed getting users followed by {seed}: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+    let mut sources = fetch.seeds.len();
+    if config.lists.iter().any(|l| l.members) {
+        sources += 1;
+        match get_list_users(pool, config).await {
+            Ok(users) => found.extend(users),
+            Err(e) => {
+                warn!("Failed getting list members: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+    if sources != 0 && failed == sources {
+        bail!("Failed getting users from every seed and list");
+    }
+    found.extend(
+        fetch
+            .users
+            .iter()
+            .map(|u| (u.to_owned(), TrackReason::Listed)),
+    );
+
+    // Usernames are case insensitive, so the first spelling seen is kept
+    let mut tracked: IndexMap<String, Vec<TrackReason>> = IndexMap::new();
+    let mut spellings = HashMap::new();
+    for (user, reason) in found {
+        let lowercase = user.to_lowercase();
+        if fetch.exclude.iter().any(|e| e.to_lowercase() == lowercase) {
+            debug!("Not archiving {user}, as it is excluded");
+            continue;
+        }
+        let user = spellings.entry(lowercase).or_insert(user);
+        let reasons = tracked.entry(user.clone()).or_default();
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
+    }
+    if tracked.is_empty() {
+        warn!("No users to archive, add some seeds or users to the config");
+    }
+    db.set_tracked_users(&tracked)
+        .await
+        .wrap_err("Failed saving tracked users")?;
+    Ok(tracked.into_keys().collect())
+}
+
+/// Archives the latest posts found by each search in the config, and in the
+/// timeline of each list archived as a whole. Both require logging in.
+async fn archive_searches_and_lists(
+    pool: &DriverPool,
+    http: &hls::HttpClient,
+    db: &Db,
+    config: &Config,
+) -> Result<()> {
+    let lists = config
+        .lists
+        .iter()
+        .filter(|l| l.timeline)
+        .collect::<Vec<_>>();
+    if config.searches.is_empty() && lists.is_empty() {
+        return Ok(());
+    }
+    let c = pool
+        .wait_for_client(&config.twitter_config, ClientAuth::LoggedIn)
+        .await
+        .wrap_err("Could not get client")?;
+    for search in &config.searches {
+        info!("Searching for {}", search.query);
+        let source = Source::Search { name: &search.name };
+        let archived = db.get_archived(source).await?;
+        match get_posts_from_search(&c, &search.query, config, &archived).await {
+            Ok(posts) => save_posts(&c, http, db, config, source, posts).await?,
+            Err(e) => warn!("Failed searching for {}: {e:#}", search.query),
+        }
+    }
+    for list in lists {
+        info!("Fetching the timeline of list {}", list.name);
+        let source = Source::List { id: &list.id };
+        let archived = db.get_archived(source).await?;
+        match get_posts_from_list(&c, &list.id, config, &archived).await {
+            Ok(posts) => save_posts(&c, http, db, config, source, posts).await?,
+            Err(e) => warn!("Failed fetching the timeline of list {}: {e:#}", list.name),
+        }
+    }
+    c.close().await?;
+    Ok(())
+}
+
+/// Revisits some of the archived posts, and marks the ones that are gone as
+/// deleted.
+async fn verify_posts(pool: &DriverPool, db: &Db, config: &Config, auth: ClientAuth) -> Result<()> {
+    let verify = &config.fetch_config.verify;
+    let links = db
+        .get_posts_to_verify(
+            verify.posts_per_run,
+            chrono::Duration::days(verify.max_age_days),
+        )
+        .await?;
+    if links.is_empty() {
+        return Ok(());
+    }
+    info!("Checking whether {} posts are still up", links.len());
+    let c = pool
+        .wait_for_client(&config.twitter_config, auth)
+        .await
+        .wrap_err("Could not get client")?;
+    let mut client = Some(c);
+    let mut deleted = 0;
+    for link in links {
+        let revisions = match split_status_link(&link) {
+            Some((_, id)) => db.get_revisions(id).await?,
+            None => vec![],
+        };
+        let c = client.as_ref().unwrap();
+        let guest = c.auth() == ClientAuth::Guest;
+        let mut res = get_post(c, &link, false, &revisions).await;
+        if guest && matches!(&res, Err(e) if e.kind() == FetchErrorKind::LoginRequired) {
+            debug!("{link} requires logging in, retrying with a logged in client");
+            let c = log_in(pool, config, &mut client)
+                .await
+                .wrap_err("No logged in client to verify posts with")?;
+            res = get_post(c, &link, false, &revisions).await;
+        }
+        match res {
+            Ok(_) => db.mark_post_checked(&link).await?,
+            Err(FetchError::PostUnavailable { reason }) => {
+                info!("{link} is gone: {reason}");
+                db.mark_post_deleted(&link, &reason).await?;
+                deleted += 1;
+            }
+            Err(e) => warn!("Failed checking {link}: {e:#}"),
+        }
+    }
+    info!("Found {deleted} deleted posts");
+    if let Some(c) = client {
+        c.close().await?;
+    }
+    Ok(())
+}
+
+/// Prints the users archived by the last fetch, and why each one is.
+async fn list_tracked(db: &Db) -> Result<()> {
+    let users = db.get_tracked_users().await?;
+    if users.is_empty() {
+        println!("No tracked users");
+    }
+    for (user, reasons) in &users {
+        let reasons = reasons.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        println!("@{user}: {}", reasons.join(", "));
+    }
+    Ok(())
+}
+
+/// Prints the archived posts that were deleted, by `user` if given.
+async fn list_deleted(db: &Db, user: Option<&str>) -> Result<()> {
+    let posts = db.get_deleted_posts(user).await?;
+    if posts.is_empty() {
+        println!("No deleted posts");
+    }
+    let mut last_user = None;
+    for post in &posts {
+        if last_user != Some(&post.username) {
+            println!("@{}:", post.username);
+            last_user = Some(&post.username);
+        }
+        println!(
+            "  {link} posted {posted}, gone since {deleted} ({reason})",
+            link = post.link,
+            posted = post.posted_at.format("%Y-%m-%d %H:%M"),
+            deleted = post.deleted_at.format("%Y-%m-%d %H:%M"),
+            reason = post.deleted_reason,
+        );
+        for line in post.text.lines() {
+            println!("    {line}");
+        }
+        if let Some(path) = &post.screenshot_path {
+            println!("    Screenshot: {path}");
+        }
+    }
     Ok(())
 }
 
@@ -119,10 +719,40 @@ async fn main() -> Result<()> {
 
     let config = Config::get().wrap_err("Failed getting config")?;
 
-    let pool = DriverPool::new(&config.driver_config).wrap_err("Failed creating pool")?;
+    if let Command::DownloadVideo {
+        playlist_url,
+        output,
+    } = &config.command
+    {
+        let ffmpeg = config.storage_config.ffmpeg.as_deref();
+        hls::download(&hls::http_client(), playlist_url, Path::new(output), ffmpeg).await?;
+        return Ok(());
+    }
+
+    let db = Db::open(&config.storage_config.database)
+        .await
+        .wrap_err("Failed opening database")?;
+    let db = Arc::new(db);
+
+    if let Command::Deleted { user } = &config.command {
+        let res = list_deleted(&db, user.as_deref()).await;
+        db.close().await;
+        return res;
+    }
+    if let Command::Tracked = &config.command {
+        let res = list_tracked(&db).await;
+        db.close().await;
+        return res;
+    }
+
+    let pool = DriverPool::new(&config.driver_config, &config.rate_limit_config)
+        .await
+        .wrap_err("Failed creating pool")?;
     let pool = Arc::new(pool);
 
-    if let e @ Err(_) = run(Arc::clone(&pool), config).await {
+    let res = run(Arc::clone(&pool), Arc::clone(&db), config).await;
+    db.close().await;
+    if let e @ Err(_) = res {
         pool.close().await.wrap_err("Failed closing drivers")?;
         return e;
     } else {
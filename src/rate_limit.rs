@@ -0,0 +1,125 @@
+use std::{fmt, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{debug, warn};
+
+use crate::config::RateLimitConfig;
+
+/// Messages the site shows instead of content when it is being rate limited.
+const LIMITED_STATES: &[&str] = &[
+    "Rate limit exceeded",
+    "Something went wrong. Try reloading.",
+];
+
+/// Returned when the site kept rate limiting us after every retry.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub state: &'static str,
+    pub retries: usize,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Still rate limited after {} retries: {}",
+            self.retries, self.state
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Returns which rate limited state the page is in, if any.
+pub fn limited_state(src: &str) -> Option<&'static str> {
+    LIMITED_STATES.iter().find(|s| src.contains(*s)).copied()
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// Set when the site rate limited us, and everyone should wait
+    paused_until: Option<Instant>,
+}
+
+/// A token bucket shared by every client using the same account, so the site
+/// sees at most `requests_per_minute` page loads and scrolls from it.
+pub struct RateLimiter {
+    name: &'static str,
+    bucket: Mutex<Bucket>,
+    per_second: f64,
+    capacity: f64,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    max_retries: usize,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, requests_per_minute: u32, config: &RateLimitConfig) -> Self {
+        let capacity = config.burst.max(1) as f64;
+        RateLimiter {
+            name,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+                paused_until: None,
+            }),
+            per_second: requests_per_minute.max(1) as f64 / 60.0,
+            capacity,
+            backoff_base: Duration::from_secs(config.backoff_base),
+            backoff_max: Duration::from_secs(config.backoff_max),
+            max_retries: config.max_retries,
+        }
+    }
+
+    pub fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    /// Waits until a request can be made.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().await;
+                let now = Instant::now();
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        bucket.paused_until = None;
+                        let elapsed = now.saturating_duration_since(bucket.last_refill);
+                        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second)
+                            .min(self.capacity);
+                        bucket.last_refill = now;
+                        if bucket.tokens >= 1.0 {
+                            bucket.tokens -= 1.0;
+                            return;
+                        }
+                        Duration::from_secs_f64((1.0 - bucket.tokens) / self.per_second)
+                    }
+                }
+            };
+            debug!("Waiting {wait:?} for the {} rate limiter", self.name);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Pauses every request through this limiter, for exponentially longer
+    /// the more `attempt`s have been made.
+    pub async fn back_off(&self, attempt: usize) {
+        let delay = self
+            .backoff_base
+            .saturating_mul(2u32.saturating_pow(attempt as u32))
+            .min(self.backoff_max);
+        warn!(
+            "Rate limited on the {} limiter, backing off for {delay:?}",
+            self.name
+        );
+        let mut bucket = self.bucket.lock().await;
+        let until = Instant::now() + delay;
+        if bucket.paused_until.map(|u| u < until).unwrap_or(true) {
+            bucket.paused_until = Some(until);
+            // Start slowly once the pause is over
+            bucket.tokens = 0.0;
+            bucket.last_refill = until;
+        }
+    }
+}
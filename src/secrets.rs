@@ -0,0 +1,124 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{env, os::unix::fs::PermissionsExt, path::Path, process::Stdio};
+use tokio::io::AsyncWriteExt;
+
+use crate::config::TwitterConfig;
+
+/// Prepended to encrypted files, so plaintext caches from older versions can
+/// still be read.
+const MAGIC: &[u8] = b"TWITARC-ENC1";
+const NONCE_LEN: usize = 12;
+
+pub const AUTH_KEY_ENV: &str = "TWITARC_AUTH_KEY";
+
+/// Loads the key for the auth cache, first from the environment, and then from
+/// the configured keyfile. The key material can be any length, as it is hashed
+/// down to 32 bytes.
+pub fn auth_cache_key(config: &TwitterConfig) -> Result<Option<Key>> {
+    let material = if let Ok(key) = env::var(AUTH_KEY_ENV) {
+        key.into_bytes()
+    } else if let Some(path) = &config.auth_cache_keyfile {
+        std::fs::read(path).wrap_err_with(|| format!("Failed reading keyfile {path}"))?
+    } else {
+        return Ok(None);
+    };
+
+    // Trailing newlines are usually an accident of how the key was written
+    let end = material
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let material = &material[..end];
+    if material.is_empty() {
+        bail!("Auth cache key is empty");
+    }
+    Ok(Some(Key::clone_from_slice(&Sha256::digest(material))))
+}
+
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(key);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| eyre!("Failed encrypting data"))?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn decrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+    let data = data
+        .strip_prefix(MAGIC)
+        .ok_or(eyre!("Data is not encrypted"))?;
+    if data.len() < NONCE_LEN {
+        bail!("Encrypted data is truncated");
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| eyre!("Failed decrypting data, the key is probably wrong"))
+}
+
+/// Writes `contents` to `path`, only readable and writable by the owner.
+pub async fn write_private_file(path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let mut f = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .await
+        .wrap_err_with(|| format!("Failed opening {}", path.display()))?;
+    // `mode` only applies when the file is created, so fix up old files
+    f.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .await
+        .wrap_err("Failed setting file permissions")?;
+    f.write_all(contents).await?;
+    f.flush().await?;
+    Ok(())
+}
+
+/// Runs `command` through the shell and returns the first line of its output.
+pub fn run_password_command(command: &str) -> Result<String> {
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .wrap_err("Failed running password command")?;
+    if !output.status.success() {
+        bail!("Password command exited with {}", output.status);
+    }
+
+    let stdout = String::from_utf8(output.stdout).wrap_err("Password was not UTF-8")?;
+    let password = stdout.lines().next().unwrap_or_default();
+    if password.is_empty() {
+        bail!("Password command returned no output");
+    }
+    Ok(password.to_owned())
+}
+
+pub fn read_password_file(path: &str) -> Result<String> {
+    let contents =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("Failed reading {path}"))?;
+    let password = contents.lines().next().unwrap_or_default();
+    if password.is_empty() {
+        bail!("Password file {path} is empty");
+    }
+    Ok(password.to_owned())
+}
@@ -28,3 +28,19 @@ pub fn has_classes(e: scraper::ElementRef, classes: &[&str]) -> bool {
 pub fn get_user_link(username: &str) -> String {
     format!("https://twitter.com/{username}")
 }
+
+/// Escapes `s` to be used as XML text or attribute value.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
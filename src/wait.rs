@@ -0,0 +1,118 @@
+use color_eyre::eyre::{Context, Result};
+use fantoccini::{elements::Element, error::CmdError, Client, Locator};
+use std::{fmt, future::Future, time::Duration};
+use tokio::time::Instant;
+use tracing::debug;
+
+/// How long to wait for a condition before giving up.
+pub const TIMEOUT: Duration = Duration::from_secs(20);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the page must go without loading anything to be considered idle.
+const IDLE_TIME: Duration = Duration::from_millis(750);
+
+/// Returned when a condition isn't met in time.
+#[derive(Debug)]
+pub struct Timeout {
+    what: String,
+    after: Duration,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {:?} waiting for {}",
+            self.after, self.what
+        )
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// True once at least one post has been rendered on the page.
+pub const POSTS_RENDERED: &str = "return document.querySelector('article') !== null;";
+
+/// Polls `check` until it returns `Some`, or `timeout` passes.
+pub async fn until<T, F, Fut>(what: &str, timeout: Duration, mut check: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<T>>>,
+{
+    let start = Instant::now();
+    loop {
+        if let Some(v) = check().await? {
+            debug!("Waited {:?} for {what}", start.elapsed());
+            return Ok(v);
+        }
+        if start.elapsed() > timeout {
+            return Err(Timeout {
+                what: what.to_owned(),
+                after: timeout,
+            }
+            .into());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Waits for an element matching `locator` to be in the page, and returns it.
+pub async fn for_element(c: &Client, locator: Locator<'_>) -> Result<Element> {
+    let what = match locator {
+        Locator::Css(s) | Locator::Id(s) | Locator::LinkText(s) | Locator::XPath(s) => s,
+    };
+    until(what, TIMEOUT, || async {
+        match c.find(locator).await {
+            Ok(e) => Ok(Some(e)),
+            Err(CmdError::NoSuchElement(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    })
+    .await
+}
+
+/// Waits for the JS `predicate` to return true. It is run as the body of a
+/// function, so it must `return` its value.
+pub async fn for_script(c: &Client, predicate: &str) -> Result<()> {
+    until(predicate, TIMEOUT, || async {
+        let res = c
+            .execute(predicate, vec![])
+            .await
+            .wrap_err("Failed running wait predicate")?;
+        Ok(res.as_bool().unwrap_or(false).then_some(()))
+    })
+    .await
+}
+
+/// Waits for the page to finish loading, and for no new resources to be
+/// fetched for a while. Useful after scrolling, where there is nothing
+/// specific to wait for.
+pub async fn for_network_idle(c: &Client) -> Result<()> {
+    let script = "return [document.readyState, performance.getEntriesByType('resource').length];";
+    let start = Instant::now();
+    let mut last_count = None;
+    let mut last_change = start;
+    loop {
+        let res = c
+            .execute(script, vec![])
+            .await
+            .wrap_err("Failed checking network activity")?;
+        let ready = res.get(0).and_then(|v| v.as_str()) == Some("complete");
+        let count = res.get(1).and_then(|v| v.as_u64());
+        if count != last_count {
+            last_count = count;
+            last_change = Instant::now();
+        }
+        if ready && last_change.elapsed() >= IDLE_TIME {
+            debug!("Waited {:?} for network idle", start.elapsed());
+            return Ok(());
+        }
+        if start.elapsed() > TIMEOUT {
+            return Err(Timeout {
+                what: "network idle".to_owned(),
+                after: TIMEOUT,
+            }
+            .into());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}